@@ -0,0 +1,275 @@
+//! Finds mistakes in a program without running it, by looking at every mail
+//! sent with a literal subject to a literal user.
+use instruction::{Instruction, InstructionType, CondBlock};
+use interpreter::Interpreter;
+use types::Type;
+use user::{UserPath, UserType, UserDef};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+pub enum WarningType {
+	/// Mail is sent to a user on a server that is never defined.
+	UndefinedServer(UserPath),
+	/// Mail is sent to a user that is never defined.
+	UndefinedUser(UserPath),
+	/// Mail is sent with a subject that none of the user's handlers match.
+	UnmatchedSubject(UserPath, String)
+}
+
+pub struct Warning {
+	pub pos: Option<(usize, usize)>,
+	/// The imported file the warning is in, or None for the file being checked.
+	pub file: Option<String>,
+	pub warningtype: WarningType
+}
+
+impl fmt::Display for Warning {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Warning{}{}! {}.", match self.file {
+			Some(ref file) => format!(" in {}", file),
+			None => "".to_string()
+		}, match self.pos {
+			Some(pos) => format!(" on line {}:{}", pos.0, pos.1),
+			None => "".to_string()
+		}, self.warningtype)
+	}
+}
+
+impl fmt::Display for WarningType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			WarningType::UndefinedServer(ref path) => {
+				write!(f, "Mail is sent to <{:?}>, but the server {} is never defined", path, path.1)
+			},
+			WarningType::UndefinedUser(ref path) => {
+				write!(f, "Mail is sent to <{:?}>, which is never defined", path)
+			},
+			WarningType::UnmatchedSubject(ref path, ref subject) => {
+				write!(f, "Mail is sent to <{:?}> with the subject '{}', which none of its handlers match",
+					path, subject)
+			}
+		}
+	}
+}
+
+/// Returns the value of a type if it is literal text.
+fn literal(value: &Type) -> Option<&str> {
+	match *value {
+		Type::Text(ref val) => Some(val),
+		_ => None
+	}
+}
+
+/// Returns the subject of a draft if it is literal.
+fn literal_subject(draft: &Type) -> Option<&str> {
+	match *draft {
+		Type::Tuple(ref t) if t.len() == 0 => Some(""),
+		Type::Tuple(ref t) => literal(&t[0]),
+		ref other => literal(other)
+	}
+}
+
+struct Analyzer<'a> {
+	servers: HashSet<String>,
+	/// The regexes of every handler of every user, or None for native users
+	/// and users with an else block, which may accept any subject.
+	users: HashMap<(String, String), Option<Vec<Regex>>>,
+	/// Servers that users with names only known at runtime are added to.
+	dynamic_users: HashSet<String>,
+	/// True if servers, or users on them, are added with names only known at
+	/// runtime, in which case no user can be said to be undefined.
+	dynamic_servers: bool,
+	/// The imported file being walked, or None for the file being checked.
+	file: Option<&'a str>,
+	sends: Vec<(Option<&'a str>, Option<(usize, usize)>, UserPath, Option<&'a str>)>
+}
+
+impl<'a> Analyzer<'a> {
+	/// Returns the regexes of a user's handlers, or None if it may accept any subject.
+	fn get_regexes(&self, userdef: &UserDef, parent: Option<&Type>) -> Option<Vec<Regex>> {
+		let mut regexes = match *userdef.func {
+			UserType::External(_) => return None,
+			// A user with an else block accepts any subject
+			UserType::Internal(ref handlers) => match handlers.iter().map(|v|v.regex.clone()).collect::<Option<Vec<Regex>>>() {
+				Some(val) => val,
+				None => return None
+			}
+		};
+		if let Some(&Type::UserPath(ref name, ref server)) = parent {
+			match (literal(name), literal(server)) {
+				(Some(name), Some(server)) => {
+					match self.users.get(&(name.to_string(), server.to_string())) {
+						Some(&Some(ref inherited)) => regexes.extend(inherited.iter().cloned()),
+						_ => return None
+					}
+				},
+				_ => return None
+			}
+		}
+		Some(regexes)
+	}
+
+	fn add_user(&mut self, name: &str, server: &str, regexes: Option<Vec<Regex>>) {
+		let key = (name.to_string(), server.to_string());
+		// A user may be defined more than once, so keep every handler
+		let entry = self.users.entry(key).or_insert(Some(Vec::new()));
+		match (entry.as_mut(), regexes) {
+			(Some(existing), Some(mut regexes)) => existing.append(&mut regexes),
+			_ => *entry = None
+		}
+	}
+
+	fn walk_block(&mut self, block: &'a [Instruction]) {
+		for inst in block {
+			self.walk(inst);
+		}
+	}
+
+	fn walk_type(&mut self, value: &'a Type) {
+		match *value {
+			Type::Expression(ref inst) => self.walk(inst),
+			Type::Tuple(ref t) => for v in t {
+				self.walk_type(v);
+			},
+			Type::UserPath(ref name, ref server) => {
+				self.walk_type(name);
+				self.walk_type(server);
+			},
+			_ => {}
+		}
+	}
+
+	fn walk_condblock(&mut self, condblock: &'a CondBlock) {
+		if let Some(ref cond) = condblock.cond {
+			self.walk_type(cond);
+		}
+		self.walk_block(&condblock.block);
+		if let Some(ref elseblock) = condblock.elseblock {
+			self.walk_condblock(elseblock);
+		}
+	}
+
+	fn walk(&mut self, inst: &'a Instruction) {
+		match inst.insttype {
+			InstructionType::CreateServer(ref name, _) => {
+				match literal(name) {
+					Some(name) => {
+						self.servers.insert(name.to_string());
+					},
+					None => self.dynamic_servers = true
+				}
+				self.walk_type(name);
+			},
+			InstructionType::CreateUser(ref name, ref server, ref userdef, ref parent) => {
+				match (literal(name), literal(server)) {
+					(Some(name), Some(server)) => {
+						let regexes = self.get_regexes(userdef, parent.as_ref());
+						self.add_user(name, server, regexes);
+					},
+					(None, Some(server)) => {
+						self.dynamic_users.insert(server.to_string());
+					},
+					(_, None) => self.dynamic_servers = true
+				}
+				self.walk_type(name);
+				self.walk_type(server);
+				if let Some(ref parent) = *parent {
+					self.walk_type(parent);
+				}
+				if let UserType::Internal(ref handlers) = *userdef.func {
+					for handler in handlers {
+						self.walk_block(&handler.block);
+					}
+				}
+			},
+			InstructionType::MailTo(ref draft, ref target) => {
+				if let Type::UserPath(ref name, ref server) = *target {
+					if let (Some(name), Some(server)) = (literal(name), literal(server)) {
+						self.sends.push((self.file, inst.pos, UserPath(name.to_string(), server.to_string()),
+							literal_subject(draft)));
+					}
+				}
+				self.walk_type(draft);
+				self.walk_type(target);
+			},
+			InstructionType::Concatenate(ref a, ref b) |
+			InstructionType::Index(ref a, ref b) |
+			InstructionType::Assign(ref a, ref b) |
+			InstructionType::Modify(ref a, ref b) => {
+				self.walk_type(a);
+				self.walk_type(b);
+			},
+			InstructionType::GetEnv(ref a) => self.walk_type(a),
+			InstructionType::Slice(ref a, ref b, ref c) => {
+				self.walk_type(a);
+				if let Some(ref b) = *b {
+					self.walk_type(b);
+				}
+				if let Some(ref c) = *c {
+					self.walk_type(c);
+				}
+			},
+			InstructionType::IfBlock(ref condblock) => self.walk_condblock(condblock),
+			InstructionType::Import(_, ref name, ref block) => {
+				let file = ::std::mem::replace(&mut self.file, Some(name));
+				self.walk_block(block);
+				self.file = file;
+			},
+			InstructionType::Continue => {}
+		}
+	}
+
+	fn check_send(&self, path: &UserPath, subject: Option<&str>) -> Option<WarningType> {
+		if path.is_anon() || self.dynamic_servers || self.dynamic_users.contains(&path.1) {
+			return None;
+		}
+		let regexes = match self.users.get(&(path.0.clone(), path.1.clone())) {
+			Some(val) => val,
+			None if !self.servers.contains(&path.1) => {
+				return Some(WarningType::UndefinedServer(path.clone()))
+			},
+			None => return Some(WarningType::UndefinedUser(path.clone()))
+		};
+		match (regexes.as_ref(), subject) {
+			(Some(regexes), Some(subject)) if !regexes.iter().any(|v|v.is_match(subject)) => {
+				Some(WarningType::UnmatchedSubject(path.clone(), subject.to_string()))
+			},
+			_ => None
+		}
+	}
+}
+
+/// Analyzes a program that would be run by the given interpreter. Users that
+/// already exist in the interpreter, such as the standard library, are taken
+/// into account. Returns the warnings in the order they appear in the program.
+pub fn analyze(instructions: &[Instruction], inter: &Interpreter) -> Vec<Warning> {
+	let mut analyzer = Analyzer {
+		servers: HashSet::new(),
+		users: HashMap::new(),
+		dynamic_users: HashSet::new(),
+		dynamic_servers: false,
+		file: None,
+		sends: Vec::new()
+	};
+	for server in inter.get_server_names() {
+		analyzer.servers.insert(server.to_string());
+		let serv = inter.get_server(server).unwrap();
+		for name in serv.get_user_names() {
+			let regexes = analyzer.get_regexes(&serv.get_user(name).unwrap().get_userdef(), None);
+			analyzer.add_user(name, server, regexes);
+		}
+	}
+	analyzer.walk_block(instructions);
+
+	let mut warnings = analyzer.sends.iter().filter_map(|&(file, pos, ref path, subject)| {
+		analyzer.check_send(path, subject).map(|warningtype|Warning {
+			pos: pos,
+			file: file.map(|v|v.to_string()),
+			warningtype: warningtype
+		})
+	}).collect::<Vec<Warning>>();
+	// Warnings in imported files come after the warnings in the checked file
+	warnings.sort_by_key(|v|(v.file.clone(), v.pos));
+	warnings
+}
@@ -1,5 +1,8 @@
 use std::fmt;
 use symbols::{SymbolDef};
+use user::UserPath;
+use mail::{Mail, BounceReason};
+use std::time::Duration;
 
 #[derive(Clone, Debug)]
 pub struct SyntaxErrorFactory {
@@ -30,7 +33,8 @@ impl SyntaxErrorFactory {
 	pub fn gen_error(&self, errortype: SyntaxErrorType) -> SyntaxError {
 		SyntaxError {
 			errortype: errortype,
-			pos: self.pos
+			pos: self.pos,
+			file: None
 		}
 	}
 
@@ -51,11 +55,29 @@ pub enum SyntaxErrorType {
 	NotAType,
 	BadExpression,
 	BadUserBlock,
-	BadDefinition(Option<String>)
+	BadDefinition(Option<String>),
+	InvalidEscape(String),
+	InvalidRegex(String),
+	ExpectedComma,
+	ExpectedBlock,
+	MissingCondition,
+	ElseWithCondition,
+	ElseNotLast,
+	UnexpectedIf,
+	BadFilterRule,
+	BadImport,
+	ImportNotAtTopLevel,
+	ImportCycle(String),
+	ImportFailed(String, String),
+	ExpectedParent,
+	DuplicateElse,
+	ReservedGroupName(String)
 }
 
 pub struct SyntaxError {
 	pub pos: Option<(usize, usize)>,
+	/// The imported file the error is in, or None for the file being parsed.
+	pub file: Option<String>,
 	pub errortype: SyntaxErrorType
 }
 
@@ -63,20 +85,32 @@ impl SyntaxError {
 	pub fn new(line: usize, column: usize, errortype: SyntaxErrorType) -> SyntaxError {
 		SyntaxError {
 			errortype: errortype,
-			pos: Some((line, column))
+			pos: Some((line, column)),
+			file: None
 		}
 	}
 	pub fn new_eof(errortype: SyntaxErrorType) -> SyntaxError {
 		SyntaxError {
 			errortype: errortype,
-			pos: None
+			pos: None,
+			file: None
 		}
 	}
 }
 
+// Allows single errors to be returned with try! where many errors are expected
+impl From<SyntaxError> for Vec<SyntaxError> {
+	fn from(err: SyntaxError) -> Vec<SyntaxError> {
+		vec![err]
+	}
+}
+
 impl fmt::Display for SyntaxError {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		write!(f, "Syntax Error {}! {}.", match self.pos {
+		write!(f, "Syntax Error {}{}! {}.", match self.file {
+			Some(ref file) => format!("in {} ", file),
+			None => "".to_string()
+		}, match self.pos {
 			Some(pos) => format!("on line {}:{}", pos.0, pos.1),
 			None => "at end of file".to_string()
 		}, self.errortype)
@@ -99,6 +133,219 @@ impl fmt::Display for SyntaxErrorType {
 					None => "".to_string()
 				})
 			},
+			SyntaxErrorType::InvalidEscape(ref s) => write!(f, "Invalid escape sequence '{}'", s),
+			SyntaxErrorType::InvalidRegex(ref s) => write!(f, "Invalid regex '{}'", s),
+			SyntaxErrorType::ExpectedComma => {
+				write!(f, "Expected comma, values in parenthesis must be separated by commas")
+			},
+			SyntaxErrorType::ExpectedBlock => write!(f, "Expected a block in curly braces"),
+			SyntaxErrorType::MissingCondition => write!(f, "Missing condition"),
+			SyntaxErrorType::ElseWithCondition => {
+				write!(f, "An else block can not have a condition, use elif instead")
+			},
+			SyntaxErrorType::ElseNotLast => write!(f, "Nothing can follow an else block"),
+			SyntaxErrorType::UnexpectedIf => {
+				write!(f, "Unexpected if, use elif to continue an if statement")
+			},
+			SyntaxErrorType::BadFilterRule => {
+				write!(f, "Bad filter rule, expected block, allow, rate, bounce or log")
+			},
+			SyntaxErrorType::BadImport => write!(f, "Bad import, expected a file name in quotes"),
+			SyntaxErrorType::ImportNotAtTopLevel => {
+				write!(f, "Files can only be imported outside of blocks")
+			},
+			SyntaxErrorType::ImportCycle(ref s) => write!(f, "'{}' is already being imported", s),
+			SyntaxErrorType::ImportFailed(ref s, ref err) => {
+				write!(f, "Could not import '{}': {}", s, err)
+			},
+			SyntaxErrorType::ExpectedParent => {
+				write!(f, "Expected a user to inherit from after ':'")
+			},
+			SyntaxErrorType::DuplicateElse => write!(f, "A user can only have one else block"),
+			SyntaxErrorType::ReservedGroupName(ref s) => {
+				write!(f, "The capture group name '{}' is reserved for the mail's variables", s)
+			},
+		}
+	}
+}
+
+#[derive(Debug)]
+pub enum RuntimeErrorType {
+	TypeMismatch(&'static str, &'static str),
+	IndexOutOfRange(isize, usize),
+	NotANumber(String),
+	UnknownModifier(String),
+	BadModifierArguments(String),
+	BadRegex(String),
+	BadAssignment,
+	UndefinedServer(String),
+	UndefinedUser(UserPath),
+	NativeParent(UserPath),
+	/// `continue` was used outside of a handler, where there is no next handler.
+	ContinueOutsideHandler,
+	Undeliverable(UserPath, String, BounceReason),
+	AssertionsFailed(Vec<AssertionFailure>),
+	/// Execution was stopped by a limit, after delivering the given mail.
+	LimitExceeded(Limit, Vec<Mail>)
+}
+
+/// A limit on how long a program may run.
+#[derive(Clone, Copy, Debug)]
+pub enum Limit {
+	Frames(usize),
+	Mails(usize),
+	Pending(usize),
+	Timeout(Duration)
+}
+
+impl fmt::Display for Limit {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Limit::Frames(n) => write!(f, "the limit of {} frames", n),
+			Limit::Mails(n) => write!(f, "the limit of {} mails", n),
+			Limit::Pending(n) => write!(f, "the limit of {} pending mails", n),
+			Limit::Timeout(t) => write!(f, "the time limit of {:?}", t)
+		}
+	}
+}
+
+/// An assertion made with `<assert@std.com>` that did not hold.
+#[derive(Clone, Debug)]
+pub struct AssertionFailure {
+	pub from: UserPath,
+	pub message: String
+}
+
+impl fmt::Display for AssertionFailure {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "<{:?}>: {}", self.from, self.message)
+	}
+}
+
+/// The user handler that was being executed when a runtime error occurred.
+#[derive(Clone, Debug)]
+pub struct HandlerContext {
+	pub user: UserPath,
+	/// The handler's regex, or None for the else block.
+	pub regex: Option<String>,
+	pub pos: Option<(usize, usize)>
+}
+
+impl fmt::Display for HandlerContext {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "in {} of <{:?}>{}", match self.regex {
+			Some(ref regex) => format!("handler \"{}\"", regex),
+			None => "the else block".to_string()
+		}, self.user, match self.pos {
+			Some(pos) => format!(" (line {}:{})", pos.0, pos.1),
+			None => "".to_string()
+		})
+	}
+}
+
+#[derive(Debug)]
+pub struct RuntimeError {
+	pub pos: Option<(usize, usize)>,
+	/// The imported file the error is in, or None for the file being run.
+	pub file: Option<String>,
+	pub handler: Option<HandlerContext>,
+	pub errortype: RuntimeErrorType
+}
+
+impl RuntimeError {
+	pub fn new(errortype: RuntimeErrorType) -> RuntimeError {
+		RuntimeError {
+			errortype: errortype,
+			handler: None,
+			pos: None,
+			file: None
+		}
+	}
+
+	/// Sets the position of this error, unless a more precise one is already known.
+	pub fn or_pos(mut self, pos: Option<(usize, usize)>) -> RuntimeError {
+		if self.pos.is_none() {
+			self.pos = pos;
+		}
+		self
+	}
+
+	/// Sets the imported file this error occurred in, unless one is already known.
+	pub fn or_file(mut self, file: &str) -> RuntimeError {
+		if self.file.is_none() {
+			self.file = Some(file.to_string());
+		}
+		self
+	}
+
+	/// Sets the handler this error occurred in, unless one is already known.
+	pub fn or_handler(mut self, handler: HandlerContext) -> RuntimeError {
+		if self.handler.is_none() {
+			self.handler = Some(handler);
+		}
+		self
+	}
+}
+
+impl fmt::Display for RuntimeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "Runtime Error{}{}{}! {}.", match self.file {
+			Some(ref file) => format!(" in {}", file),
+			None => "".to_string()
+		}, match self.pos {
+			Some(pos) => format!(" on line {}:{}", pos.0, pos.1),
+			None => "".to_string()
+		}, match self.handler {
+			Some(ref handler) => format!(" {}", handler),
+			None => "".to_string()
+		}, self.errortype)
+	}
+}
+
+impl fmt::Display for RuntimeErrorType {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			RuntimeErrorType::TypeMismatch(ref expected, ref found) => {
+				write!(f, "Type mismatch, expected {} but found {}", expected, found)
+			},
+			RuntimeErrorType::IndexOutOfRange(ref pos, ref len) => {
+				write!(f, "Index {} is out of range for length {}", pos, len)
+			},
+			RuntimeErrorType::NotANumber(ref s) => write!(f, "'{}' is not a number", s),
+			RuntimeErrorType::UnknownModifier(ref s) => write!(f, "Unknown modifier '{}'", s),
+			RuntimeErrorType::BadModifierArguments(ref s) => {
+				write!(f, "Bad arguments given to modifier '{}'", s)
+			},
+			RuntimeErrorType::BadRegex(ref s) => write!(f, "Invalid regex '{}'", s),
+			RuntimeErrorType::BadAssignment => write!(f, "Bad assignment"),
+			RuntimeErrorType::UndefinedServer(ref s) => write!(f, "Server '{}' is not defined", s),
+			RuntimeErrorType::UndefinedUser(ref path) => write!(f, "User <{:?}> is not defined", path),
+			RuntimeErrorType::NativeParent(ref path) => {
+				write!(f, "Can not inherit from <{:?}>, which is not defined in Emailang", path)
+			},
+			RuntimeErrorType::ContinueOutsideHandler => write!(f, "'continue' can only be used in a handler"),
+			RuntimeErrorType::Undeliverable(ref to, ref subject, ref reason) => {
+				write!(f, "Could not deliver mail '{}' to <{:?}>, {}", subject, to, reason)
+			},
+			RuntimeErrorType::AssertionsFailed(ref failures) => {
+				try!(write!(f, "{} assertion{} failed", failures.len(),
+					if failures.len() == 1 {""} else {"s"}));
+				for failure in failures {
+					try!(write!(f, "\n    {}", failure));
+				}
+				Ok(())
+			},
+			RuntimeErrorType::LimitExceeded(ref limit, ref mail) => {
+				try!(write!(f, "Exceeded {}", limit));
+				if mail.len() > 0 {
+					try!(write!(f, ", the last {} delivered {}", if mail.len() == 1 {"mail"} else {"mails"},
+						if mail.len() == 1 {"was"} else {"were"}));
+				}
+				for m in mail {
+					try!(write!(f, "\n    {}", m));
+				}
+				Ok(())
+			},
 		}
 	}
 }
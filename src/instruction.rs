@@ -1,12 +1,15 @@
 // use server;
+use server::Filter;
 use user::*;
 use mail::*;
 use interpreter::Interpreter;
 use types::Type;
 use environment::Environment;
+use error::{RuntimeError, RuntimeErrorType};
 
 #[derive(Debug, Clone)]
 pub struct CondBlock {
+	pub pos: Option<(usize, usize)>,
 	pub cond: Option<Type>,
 	pub block: Vec<Instruction>,
 	pub elseblock: Option<Box<CondBlock>>
@@ -21,26 +24,77 @@ impl CondBlock {
 		}
 	}
 
-	pub fn call(&self, inter: &mut Interpreter, from: &UserPath, env: &mut Environment) {
+	/// Returns true if this chain of blocks ends with an else block.
+	pub fn has_else(&self) -> bool {
+		match self.elseblock {
+			Some(ref eb) => eb.has_else(),
+			None => self.cond.is_none()
+		}
+	}
+
+	/// Marks every user defined in this chain of blocks as defined in the
+	/// given imported file.
+	pub fn set_file(&mut self, file: &str) {
+		for inst in &mut self.block {
+			inst.set_file(file);
+		}
+		if let Some(ref mut eb) = self.elseblock {
+			eb.set_file(file);
+		}
+	}
+
+	pub fn call(&self, inter: &mut Interpreter, from: &UserPath,
+	            env: &mut Environment) -> Result<(), RuntimeError> {
 		let do_thing = match self.cond {
 			None => true,
-			Some(ref t) => t.get_bool(inter, from, env)
+			Some(ref t) => try!(t.get_bool(inter, from, env).map_err(|err|err.or_pos(self.pos)))
 		};
 		if do_thing {
-			inter.run(&self.block, from, env);
+			inter.run(&self.block, from, env)
 		} else {
 			match self.elseblock {
 				Some(ref eb) => eb.call(inter, from, env),
-				None => {}
+				None => Ok(())
 			}
 		}
 	}
 }
 
 #[derive(Clone, Debug)]
-pub enum Instruction {
-	CreateServer(Type),
-	CreateUser(Type, Type, UserDef),
+pub struct Instruction {
+	pub pos: Option<(usize, usize)>,
+	pub insttype: InstructionType
+}
+
+impl Instruction {
+	pub fn new(insttype: InstructionType, pos: Option<(usize, usize)>) -> Instruction {
+		Instruction {
+			insttype: insttype,
+			pos: pos
+		}
+	}
+
+	pub fn call(&self, inter: &mut Interpreter, from: &UserPath,
+	            env: &mut Environment) -> Result<Type, RuntimeError> {
+		self.insttype.call(inter, from, env).map_err(|err|err.or_pos(self.pos))
+	}
+
+	/// Marks every user defined by this instruction as defined in the given
+	/// imported file, so that errors in their handlers can be found.
+	pub fn set_file(&mut self, file: &str) {
+		match self.insttype {
+			InstructionType::CreateUser(_, _, ref mut userdef, _) => userdef.set_file(file),
+			InstructionType::IfBlock(ref mut condblock) => condblock.set_file(file),
+			_ => {}
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub enum InstructionType {
+	CreateServer(Type, Filter),
+	/// A user's name, server, definition and the user it inherits from.
+	CreateUser(Type, Type, UserDef, Option<Type>),
 	MailTo(Type, Type),
 	Concatenate(Type, Type),
 	GetEnv(Type),
@@ -48,24 +102,40 @@ pub enum Instruction {
 	Index(Type, Type),
 	Assign(Type, Type),
 	IfBlock(CondBlock),
-	Modify(Type, Type)
+	Modify(Type, Type),
+	/// The full path of an imported file, its name as it was imported, and its
+	/// top level block.
+	Import(String, String, Vec<Instruction>),
+	/// Stops the handler, and tries the next handler that matches the subject.
+	Continue
 }
 
-impl Instruction {
-	pub fn call(&self, inter: &mut Interpreter, from: &UserPath, env: &mut Environment) -> Type {
+impl InstructionType {
+	pub fn call(&self, inter: &mut Interpreter, from: &UserPath,
+	            env: &mut Environment) -> Result<Type, RuntimeError> {
 		match *self {
-			Instruction::CreateServer(ref name) => {
-				let servername = name.get_string(inter, from, env).unwrap();
-				inter.add_server(&servername);
+			InstructionType::CreateServer(ref name, ref filter) => {
+				let servername = try!(name.get_string(inter, from, env));
+				inter.add_filtered_server(&servername, filter.clone());
 			},
-			Instruction::CreateUser(ref name, ref server, ref userdef) => {
-				let username = name.get_string(inter, from, env).unwrap();
-				let servername = server.get_string(inter, from, env).unwrap();
-				inter.add_user(&username, &servername, userdef);
+			InstructionType::CreateUser(ref name, ref server, ref userdef, ref parent) => {
+				let username = try!(name.get_string(inter, from, env));
+				let servername = try!(server.get_string(inter, from, env));
+				match *parent {
+					Some(ref parent) => {
+						let parent = try!(parent.get_user(inter, from, env));
+						let parentdef = try!(inter.get_userdef(&parent).ok_or(
+							RuntimeError::new(RuntimeErrorType::UndefinedUser(parent.clone()))));
+						let userdef = try!(userdef.extend(&parentdef).ok_or(
+							RuntimeError::new(RuntimeErrorType::NativeParent(parent))));
+						inter.add_user(&username, &servername, &userdef);
+					},
+					None => inter.add_user(&username, &servername, userdef)
+				}
 			},
-			Instruction::MailTo(ref draft, ref name) => {
-				let d = draft.get_draft(inter, from, env).unwrap();
-				let target = name.get_user(inter, from, env).unwrap();
+			InstructionType::MailTo(ref draft, ref name) => {
+				let d = try!(draft.get_draft(inter, from, env));
+				let target = try!(name.get_user(inter, from, env));
 				inter.mail(Mail {
 					subject: d.subject,
 					message: d.message,
@@ -73,90 +143,115 @@ impl Instruction {
 					to: target,
 					from: from.clone()
 				});
-				return draft.clone();
+				return Ok(draft.clone());
 			},
-			Instruction::Concatenate(ref lval, ref rval) => {
-				let lstr = lval.get_string(inter, from, env);
-				let rstr = rval.get_string(inter, from, env);
-				match (lstr, rstr) {
-					(Some(ref lstringval), Some(ref rstringval)) => {
-						return Type::Text(lstringval.clone() + rstringval);
+			InstructionType::Concatenate(ref lval, ref rval) => {
+				let lval = try!(lval.resolve(inter, from, env));
+				let rval = try!(rval.resolve(inter, from, env));
+				if lval.is_null() {
+					return Err(lval.mismatch("text or tuple"));
+				}
+				if rval.is_null() {
+					return Err(rval.mismatch("text or tuple"));
+				}
+				return Ok(match (lval, rval) {
+					(Type::Tuple(mut tleft), Type::Tuple(mut tright)) => {
+						tleft.append(&mut tright);
+						Type::Tuple(tleft)
 					},
-					(None, Some(_)) => {
-						let mut tleft = lval.get_tuple(inter, from, env).unwrap();
-						tleft.push(rval.clone());
-						return Type::Tuple(tleft);
+					(Type::Tuple(mut tleft), rval) => {
+						tleft.push(rval);
+						Type::Tuple(tleft)
 					},
-					(Some(_), None) => {
-						let mut tright = rval.get_tuple(inter, from, env).unwrap();
+					(lval, Type::Tuple(mut tright)) => {
 						let mut tleft:Vec<Type> = Vec::new();
-						tleft.push(lval.clone());
-						tleft.append(&mut tright);
-						return Type::Tuple(tleft);
-					},
-					(None, None) => {
-						let mut tleft = lval.get_tuple(inter, from, env).unwrap();
-						let mut tright = rval.get_tuple(inter, from, env).unwrap();
+						tleft.push(lval);
 						tleft.append(&mut tright);
-						return Type::Tuple(tleft);
+						Type::Tuple(tleft)
 					},
-				}
+					(lval, rval) => {
+						let lstringval = try!(lval.get_string(inter, from, env));
+						let rstringval = try!(rval.get_string(inter, from, env));
+						Type::Text(lstringval + &rstringval)
+					}
+				});
 			},
-			Instruction::GetEnv(ref val) => {
-				let rawkey = val.get_string(inter, from, env);
-				let rawtuple = val.get_tuple(inter, from, env);
-				return if let Some(tuple) = rawtuple {
-					Type::Tuple(tuple.iter().map(
-						|v|{let s = &v.get_string(inter, from, env).unwrap();env.get(s)}
-						).collect())
-				} else if let Some(key) = rawkey {
-					env.get(&key)
-				} else {
-					panic!();
-				};
+			InstructionType::GetEnv(ref val) => {
+				return Ok(match try!(val.resolve(inter, from, env)) {
+					Type::Tuple(tuple) => {
+						let mut ret = Vec::with_capacity(tuple.len());
+						for v in &tuple {
+							let s = try!(v.get_string(inter, from, env));
+							ret.push(env.get(&s));
+						}
+						Type::Tuple(ret)
+					},
+					other => {
+						let key = try!(other.get_string(inter, from, env));
+						env.get(&key)
+					}
+				});
 			},
-			Instruction::Index(ref val, ref pos) => {
-				return val.index(pos.get_num(inter, from, env).unwrap(), inter, from, env).unwrap();
+			InstructionType::Index(ref val, ref pos) => {
+				let pos = try!(pos.get_num(inter, from, env));
+				return val.index(pos, inter, from, env);
 			},
-			Instruction::Slice(ref val, ref a, ref b) => {
+			InstructionType::Slice(ref val, ref a, ref b) => {
+				let val = try!(val.resolve(inter, from, env));
 				let start = match *a {
-					Some(ref val) => val.get_num(inter, from, env).unwrap(),
+					Some(ref val) => try!(val.get_num(inter, from, env)),
 					None => 0
 				};
 				let end = match *b {
-					Some(ref val) => val.get_num(inter, from, env).unwrap(),
-					None => val.len(inter, from, env).unwrap() as isize
+					Some(ref val) => try!(val.get_num(inter, from, env)),
+					None => try!(val.len(inter, from, env)) as isize
 				};
-				return val.slice(start, end, inter, from, env).unwrap();
+				return val.slice(start, end, inter, from, env);
 			},
-			Instruction::Assign(ref to, ref val) => {
-				match to.get_string(inter, from, env) {
-					Some(ref s) => {
-						let content = val.resolve(inter, from, env);
-						env.set(s, content);
-					},
-					None => {
-						match (to.get_tuple(inter, from, env), val.get_tuple(inter, from, env)) {
-							(Some(ref tuple), Some(ref res)) => {
-								for i in 0..tuple.len() {
-									let s = &tuple[i].get_string(inter, from, env).unwrap();
-									let content = res[i].resolve(inter, from, env);
-									env.set(s, content);
-								}
-							},
-							_ => panic!()
+			InstructionType::Assign(ref to, ref val) => {
+				match try!(to.resolve(inter, from, env)) {
+					Type::Tuple(ref tuple) => {
+						let res = try!(val.get_tuple(inter, from, env));
+						if res.len() < tuple.len() {
+							return Err(RuntimeError::new(RuntimeErrorType::BadAssignment));
+						}
+						for i in 0..tuple.len() {
+							let s = &try!(tuple[i].get_string(inter, from, env));
+							let content = try!(res[i].resolve(inter, from, env));
+							env.set(s, content);
 						}
+					},
+					other => {
+						let s = &try!(other.get_string(inter, from, env));
+						let content = try!(val.resolve(inter, from, env));
+						env.set(s, content);
 					}
 				}
-				return val.clone();
+				return Ok(val.clone());
+			},
+			InstructionType::IfBlock(ref b) => {
+				try!(b.call(inter, from, env));
 			},
-			Instruction::IfBlock(ref b) => {
-				b.call(inter, from, env);
+			InstructionType::Modify(ref val, ref modifier) => {
+				return modifier.modify(val, inter, from, env);
 			},
-			Instruction::Modify(ref val, ref modifier) => {
-				return modifier.modify(val, inter, from, env).unwrap();
+			// Top level code, including imported files, is run by the anonymous user
+			InstructionType::Continue if from.is_anon() => {
+				return Err(RuntimeError::new(RuntimeErrorType::ContinueOutsideHandler));
+			},
+			InstructionType::Continue => inter.fall_through(),
+			InstructionType::Import(ref path, ref name, ref block) => {
+				// Every file is only run once, however many times it is imported
+				if inter.mark_imported(path) {
+					try!(inter.run(block, from, &mut Environment::new()).map_err(|err| {
+						// Errors without a position are shown at the import
+						if err.pos.is_some() { err.or_file(name) } else { err }
+					}));
+					// Stopping the imported file must not stop the file importing it
+					inter.take_fallthrough();
+				}
 			}
 		}
-		Type::Null
+		Ok(Type::Null)
 	}
 }
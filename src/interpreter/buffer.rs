@@ -0,0 +1,42 @@
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// An in-memory output sink. Clones share the same buffer, so one clone can
+/// be given to the interpreter while another is used to read the output.
+#[derive(Clone)]
+pub struct OutputBuffer {
+	data: Arc<Mutex<Vec<u8>>>
+}
+
+impl OutputBuffer {
+	pub fn new() -> OutputBuffer {
+		OutputBuffer {
+			data: Arc::new(Mutex::new(Vec::new()))
+		}
+	}
+
+	/// Returns everything written so far, as a string.
+	pub fn get_string(&self) -> String {
+		String::from_utf8_lossy(&self.data.lock().unwrap()).into_owned()
+	}
+
+	/// Takes everything written so far, leaving the buffer empty.
+	pub fn take_bytes(&self) -> Vec<u8> {
+		::std::mem::replace(&mut *self.data.lock().unwrap(), Vec::new())
+	}
+
+	/// Discards everything written so far.
+	pub fn clear(&self) {
+		self.data.lock().unwrap().clear();
+	}
+}
+
+impl Write for OutputBuffer {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		self.data.lock().unwrap().write(buf)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		Ok(())
+	}
+}
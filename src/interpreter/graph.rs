@@ -0,0 +1,69 @@
+use mail::Mail;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// Subject counts of every mail sent from one user to another.
+type Edges = BTreeMap<(String, String), BTreeMap<String, usize>>;
+
+/// Records who mailed whom, and with which subjects. Clones share the same
+/// record, so one clone can be given to the interpreter while another is used
+/// to write the graph.
+#[derive(Clone)]
+pub struct MailGraph {
+	edges: Arc<Mutex<Edges>>
+}
+
+/// Quotes a string for DOT.
+fn dot_string(s: &str) -> String {
+	format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+impl MailGraph {
+	pub fn new() -> MailGraph {
+		MailGraph {
+			edges: Arc::new(Mutex::new(BTreeMap::new()))
+		}
+	}
+
+	/// Records a sent mail.
+	pub fn record(&self, mail: &Mail) {
+		let mut edges = self.edges.lock().unwrap_or_else(|err|err.into_inner());
+		let subjects = edges.entry((format!("{:?}", mail.from), format!("{:?}", mail.to)))
+			.or_insert(BTreeMap::new());
+		*subjects.entry(mail.subject.clone()).or_insert(0) += 1;
+	}
+
+	/// Writes the graph in the DOT format, with users grouped by server, and
+	/// one edge for every pair of users labeled with each subject and how
+	/// many times it was sent.
+	pub fn write_dot(&self, out: &mut dyn Write) -> io::Result<()> {
+		let edges = self.edges.lock().unwrap_or_else(|err|err.into_inner());
+		let mut servers: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+		for &(ref from, ref to) in edges.keys() {
+			for user in &[from, to] {
+				let server = user.splitn(2, '@').nth(1).unwrap_or("");
+				servers.entry(server).or_insert(BTreeSet::new()).insert(user);
+			}
+		}
+
+		try!(writeln!(out, "digraph mail {{"));
+		for (server, users) in &servers {
+			try!(writeln!(out, "\tsubgraph {} {{", dot_string(&format!("cluster_{}", server))));
+			try!(writeln!(out, "\t\tlabel={};", dot_string(server)));
+			for user in users {
+				try!(writeln!(out, "\t\t{};", dot_string(user)));
+			}
+			try!(writeln!(out, "\t}}"));
+		}
+		for (&(ref from, ref to), subjects) in edges.iter() {
+			let label = subjects.iter()
+				.map(|(subject, count)|format!("{} ({})", subject, count))
+				.collect::<Vec<String>>()
+				.join("\n");
+			try!(writeln!(out, "\t{} -> {} [label={}];",
+				dot_string(from), dot_string(to), dot_string(&label)));
+		}
+		writeln!(out, "}}")
+	}
+}
@@ -1,28 +1,156 @@
 #![allow(dead_code)]
 use instruction::Instruction;
-use server::Server;
-use mail::Mail;
+use server::{Server, Filter, RejectAction};
+use mail::{Mail, BounceReason};
 use user::*;
 use environment::Environment;
 mod std;
-use std::collections::HashMap;
+pub mod buffer;
+pub mod trace;
+pub mod graph;
+use std::collections::{HashMap, HashSet};
 use modifier;
+use types::Type;
+use error::{RuntimeError, RuntimeErrorType, AssertionFailure, Limit};
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+use self::trace::{Tracer, TraceFormat, Outcome};
+use self::graph::MailGraph;
+use self::buffer::OutputBuffer;
+
+/// What to do with mail that can not be delivered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UndeliverableMode {
+	/// Silently drop the mail.
+	Drop,
+	/// Have the mailer daemon send a bounce email back to the sender.
+	Bounce,
+	/// Stop execution with a runtime error.
+	Error
+}
+
+/// How many of the most recently delivered mails are shown when a limit is exceeded.
+const RECENT_MAILS: usize = 5;
+
+/// Limits that stop programs which would otherwise run forever, e.g. a user
+/// that mails itself. They apply to each call to `execute`, and None means
+/// there is no limit.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+	/// The most frames that may deliver mail.
+	pub frames: Option<usize>,
+	/// The most mails that may be delivered in total.
+	pub mails: Option<usize>,
+	/// The most mails that may be waiting to be delivered at once.
+	pub pending: Option<usize>,
+	/// The longest execution may take.
+	pub timeout: Option<Duration>
+}
+
+/// Everything that delivering one mail produced, to be merged back into the
+/// interpreter in the order the mail was sent.
+struct Delivery {
+	index: usize,
+	pending: Vec<Mail>,
+	users_to_add: Vec<(String, String, User)>,
+	servers_to_add: Vec<(String, Filter)>,
+	failures: Vec<AssertionFailure>,
+	traced: Vec<(Mail, Outcome)>,
+	/// Output from `<io@std.com>`, if the worker's output was buffered.
+	output: Vec<u8>
+}
+
+/// Reads from stdin through its shared buffer on every call, so that input
+/// read by `<io@std.com>` and by the rest of the program never goes missing.
+struct StdinReader {
+	/// A copy of stdin's buffer, which is not consumed until `consume`.
+	peeked: Vec<u8>
+}
+
+impl io::Read for StdinReader {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		io::stdin().lock().read(buf)
+	}
+}
+
+impl BufRead for StdinReader {
+	fn fill_buf(&mut self) -> io::Result<&[u8]> {
+		self.peeked = try!(io::stdin().lock().fill_buf()).to_vec();
+		Ok(&self.peeked)
+	}
+
+	fn consume(&mut self, amt: usize) {
+		io::stdin().lock().consume(amt);
+	}
+
+	fn read_line(&mut self, buf: &mut String) -> io::Result<usize> {
+		io::stdin().lock().read_line(buf)
+	}
+}
+
+/// A user taken out of its server, along with the mail it received this frame.
+struct Inbox {
+	server: String,
+	name: String,
+	user: User,
+	mail: Vec<(usize, Mail)>
+}
 
 pub struct Interpreter {
+	pub undeliverable: UndeliverableMode,
+	/// How many threads mail is delivered on. Each user still handles its
+	/// own mail in order.
+	pub threads: usize,
+	pub limits: Limits,
 	servers: HashMap<String, Server>,
 	pending: Vec<Mail>,
 	users_to_add: Vec<(String, String, User)>,
-	servers_to_add: Vec<String>,
-	pub modifiers: HashMap<String, Box<modifier::ModifierFunc>>
+	servers_to_add: Vec<(String, Filter)>,
+	output: Arc<Mutex<Box<dyn Write + Send>>>,
+	input: Arc<Mutex<Box<dyn BufRead + Send>>>,
+	/// Holds a worker's output until it is merged, when mail is delivered on
+	/// more than one thread.
+	output_buffer: Option<OutputBuffer>,
+	failures: Vec<AssertionFailure>,
+	tracer: Option<Tracer>,
+	graph: Option<MailGraph>,
+	traced: Vec<(Mail, Outcome)>,
+	frame: usize,
+	delivered: usize,
+	recent: Vec<Mail>,
+	imported: HashSet<String>,
+	/// The definition of every user, which workers can see while the users
+	/// themselves are being delivered to.
+	userdefs: Arc<HashMap<(String, String), UserDef>>,
+	fallthrough: bool,
+	pub modifiers: HashMap<String, Arc<modifier::ModifierFunc>>
 }
 
 impl Interpreter {
 	pub fn new() -> Interpreter {
 		let mut inter = Interpreter {
+			undeliverable: UndeliverableMode::Drop,
+			threads: 1,
+			limits: Limits::default(),
 			servers: HashMap::new(),
 			pending: Vec::new(),
 			users_to_add: Vec::new(),
 			servers_to_add: Vec::new(),
+			output: Arc::new(Mutex::new(Box::new(io::stdout()))),
+			input: Arc::new(Mutex::new(Box::new(StdinReader { peeked: Vec::new() }))),
+			output_buffer: None,
+			failures: Vec::new(),
+			tracer: None,
+			graph: None,
+			traced: Vec::new(),
+			frame: 0,
+			delivered: 0,
+			recent: Vec::new(),
+			imported: HashSet::new(),
+			userdefs: Arc::new(HashMap::new()),
+			fallthrough: false,
 			modifiers: HashMap::new()
 		};
 
@@ -31,71 +159,425 @@ impl Interpreter {
 		inter
 	}
 
+	/// Creates an interpreter that delivers mail on another thread. It shares
+	/// its settings, modifiers, input and output with this one, but has no
+	/// servers, and keeps everything it produces for `take_delivery`.
+	fn create_worker(&self) -> Interpreter {
+		Interpreter {
+			undeliverable: self.undeliverable,
+			threads: 1,
+			limits: Limits::default(),
+			servers: HashMap::new(),
+			pending: Vec::new(),
+			users_to_add: Vec::new(),
+			servers_to_add: Vec::new(),
+			output: self.output.clone(),
+			input: self.input.clone(),
+			output_buffer: None,
+			failures: Vec::new(),
+			tracer: self.tracer.clone(),
+			graph: self.graph.clone(),
+			traced: Vec::new(),
+			frame: self.frame,
+			delivered: 0,
+			recent: Vec::new(),
+			imported: HashSet::new(),
+			userdefs: self.userdefs.clone(),
+			fallthrough: false,
+			modifiers: self.modifiers.clone()
+		}
+	}
+
+	/// Takes everything this interpreter produced since the last call.
+	fn take_delivery(&mut self, index: usize) -> Delivery {
+		Delivery {
+			index: index,
+			pending: self.pending.split_off(0),
+			users_to_add: self.users_to_add.split_off(0),
+			servers_to_add: self.servers_to_add.split_off(0),
+			failures: self.failures.split_off(0),
+			traced: self.traced.split_off(0),
+			output: self.output_buffer.as_ref().map(|v|v.take_bytes()).unwrap_or(Vec::new())
+		}
+	}
+
+	/// Keeps this worker's output for `take_delivery`, instead of writing it.
+	fn buffer_output(&mut self) {
+		let buffer = OutputBuffer::new();
+		self.output = Arc::new(Mutex::new(Box::new(buffer.clone())));
+		self.output_buffer = Some(buffer);
+	}
+
 	pub fn add_user(&mut self, name: &str, server: &str, userdef: &UserDef) {
 		self.users_to_add.push((name.to_string(), server.to_string(), userdef.create_user()));
 	}
 
+	/// Adds a user whose behavior is defined by a Rust function. The user's
+	/// server must exist, or be added in the same frame.
+	pub fn add_native_user<F>(&mut self, name: &str, server: &str, func: F)
+	where F: Fn(&User, &mut Interpreter, &Mail) + Send + Sync + 'static {
+		self.add_user(name, server, &UserDef::create_def_external(Box::new(func)));
+	}
+
 	pub fn add_server(&mut self, server: &str) {
-		self.servers_to_add.push(server.to_string());
+		self.add_filtered_server(server, Filter::new());
+	}
+
+	/// Adds a server whose mail must pass a spam filter before reaching its users.
+	pub fn add_filtered_server(&mut self, server: &str, filter: Filter) {
+		self.servers_to_add.push((server.to_string(), filter));
+	}
+
+	/// Adds a modifier, replacing any existing modifier with the same name.
+	pub fn add_modifier<F>(&mut self, name: &str, func: F)
+	where F: Fn(&Type, &mut Interpreter, &UserPath, &mut Environment, &[Type])
+	         -> Result<Type, RuntimeError> + Send + Sync + 'static {
+		self.modifiers.insert(name.to_string(), Arc::new(func));
+	}
+
+	/// Sets where output from `<io@std.com>` is written to.
+	pub fn set_output(&mut self, output: Box<dyn Write + Send>) {
+		self.output = Arc::new(Mutex::new(output));
+	}
+
+	/// Returns where output from `<io@std.com>` is written to. It stays locked
+	/// until the guard is dropped.
+	pub fn get_output<'a>(&'a self) -> MutexGuard<'a, Box<dyn Write + Send>> {
+		self.output.lock().unwrap_or_else(|err|err.into_inner())
+	}
+
+	/// Logs every frame and delivered mail to the given output.
+	pub fn set_trace(&mut self, format: TraceFormat, output: Box<dyn Write + Send>) {
+		self.tracer = Some(Tracer::new(format, output));
+	}
+
+	/// Records every mail sent from now on in the graph.
+	pub fn set_graph(&mut self, graph: MailGraph) {
+		self.graph = Some(graph);
+	}
+
+	/// Returns the graph that sent mail is recorded in, if there is one.
+	pub fn get_graph(&self) -> Option<&MailGraph> {
+		self.graph.as_ref()
+	}
+
+	/// Returns the definition of a user, including users that were defined
+	/// earlier in the block being run, which are only added at the start of
+	/// the next frame.
+	pub fn get_userdef(&self, path: &UserPath) -> Option<UserDef> {
+		match self.users_to_add.iter().rev().find(|v|v.0 == path.0 && v.1 == path.1) {
+			Some(def) => Some(def.2.get_userdef()),
+			None => self.userdefs.get(&(path.0.clone(), path.1.clone())).cloned()
+		}
+	}
+
+	/// Stops the block being run, so that the next matching handler is tried.
+	pub fn fall_through(&mut self) {
+		self.fallthrough = true;
+	}
+
+	/// Returns true if the handler that was just run fell through, and resets it.
+	pub fn take_fallthrough(&mut self) -> bool {
+		::std::mem::replace(&mut self.fallthrough, false)
+	}
+
+	/// Marks an imported file as run, returning false if it already was.
+	pub fn mark_imported(&mut self, path: &str) -> bool {
+		self.imported.insert(path.to_string())
+	}
+
+	/// Records what happened to a mail, if tracing is enabled. The trace is
+	/// written once the frame is merged, in the order the mail was sent.
+	fn trace_mail(&mut self, mail: &Mail, outcome: Outcome) {
+		if self.tracer.is_some() {
+			self.traced.push((mail.clone(), outcome));
+		}
+	}
+
+	/// Records an assertion that did not hold. Execution continues, but will
+	/// end with an error.
+	pub fn fail_assertion(&mut self, from: &UserPath, message: &str) {
+		self.failures.push(AssertionFailure {
+			from: from.clone(),
+			message: message.to_string()
+		});
+	}
+
+	/// Sets where `<io@std.com>` reads input from, e.g. a file or an
+	/// `io::Cursor` for tests.
+	pub fn set_input(&mut self, input: Box<dyn BufRead + Send>) {
+		self.input = Arc::new(Mutex::new(input));
+	}
+
+	/// Returns where `<io@std.com>` reads input from. It stays locked until
+	/// the guard is dropped.
+	pub fn get_input<'a>(&'a self) -> MutexGuard<'a, Box<dyn BufRead + Send>> {
+		self.input.lock().unwrap_or_else(|err|err.into_inner())
 	}
 
 	pub fn mail(&mut self, mail: Mail) {
 		// println!("Sending mail {} to {:?}!", mail.subject, mail.to);
+		if let Some(ref graph) = self.graph {
+			graph.record(&mail);
+		}
 		self.pending.push(mail);
 	}
 
-	fn get_server(&mut self, name: &str) -> Option<&mut Server> {
+	pub fn get_server(&self, name: &str) -> Option<&Server> {
+		self.servers.get(name)
+	}
+
+	fn get_server_mut(&mut self, name: &str) -> Option<&mut Server> {
 		self.servers.get_mut(name)
 	}
 
-	fn handle_sent_mail(&mut self, mail: &Mail) {
-		let tuser = &mail.to.0;
-		let tserver = &mail.to.1;
+	/// Returns the names of every server, in alphabetical order.
+	pub fn get_server_names(&self) -> Vec<&str> {
+		let mut names = self.servers.keys().map(|v|v.as_str()).collect::<Vec<&str>>();
+		names.sort();
+		names
+	}
 
-		let selfhack = self as *mut Interpreter;
-		let selfhack = unsafe {&mut*selfhack};
+	/// Returns all mail that has been sent, but not yet delivered.
+	pub fn get_pending(&self) -> &[Mail] {
+		&self.pending
+	}
 
-		let mut serv = match self.get_server(&tserver) {
-			Some(val) => val,
-			None => return
-		};
+	/// Has the mailer daemon send mail back to its sender.
+	fn bounce(&mut self, mail: &Mail, reason: BounceReason) {
+		// Never bounce a bounce, or this could go on forever
+		if mail.from != UserPath::new_mailer_daemon() {
+			let mut bounce = Mail::create(UserPath::new_mailer_daemon(), mail.from.clone(),
+				"bounce", &reason.to_string());
+			bounce.attach(&mail.subject);
+			bounce.attach(&format!("{:?}", mail.to));
+			self.mail(bounce);
+		}
+	}
 
-		let mut user = match serv.get_user_mut(&tuser) {
-			Some(val) => val,
-			None => return
+	/// Handles mail that was rejected by a server's spam filter.
+	fn handle_rejected(&mut self, mail: &Mail, action: RejectAction) {
+		match action {
+			RejectAction::Drop => {},
+			RejectAction::Bounce => self.bounce(mail, BounceReason::Rejected),
+			RejectAction::Log => {
+				let _ = writeln!(self.get_output(), "Rejected mail {}", mail);
+			}
+		}
+	}
+
+	fn handle_undeliverable(&mut self, mail: &Mail, reason: BounceReason) -> Result<(), RuntimeError> {
+		// Mail to the anonymous user has nowhere to go, and is never an error
+		if mail.to.is_anon() {
+			return Ok(());
+		}
+		match self.undeliverable {
+			UndeliverableMode::Drop => {},
+			UndeliverableMode::Bounce => self.bounce(mail, reason),
+			UndeliverableMode::Error => {
+				return Err(RuntimeError::new(RuntimeErrorType::Undeliverable(
+					mail.to.clone(), mail.subject.clone(), reason)));
+			}
+		}
+		Ok(())
+	}
+
+	/// Delivers every mail in the inboxes in the order it was sent, returning
+	/// the users along with what each delivery produced. Stops at the first
+	/// error.
+	fn deliver(mut worker: Interpreter, mut inboxes: Vec<Inbox>)
+	-> (Vec<Inbox>, Vec<Delivery>, Option<(usize, RuntimeError)>) {
+		let mut deliveries = Vec::new();
+		let mut order = inboxes.iter().enumerate()
+			.flat_map(|(i, inbox)|inbox.mail.iter().enumerate().map(move |(j, v)|(v.0, i, j)))
+			.collect::<Vec<(usize, usize, usize)>>();
+		order.sort();
+		for (index, i, j) in order {
+			let Inbox { ref mut user, ref mail, .. } = inboxes[i];
+			let mail = &mail[j].1;
+			let result = match user.send(&mut worker, mail) {
+				Ok(Outcome::Undelivered(reason)) => {
+					worker.trace_mail(mail, Outcome::Undelivered(reason));
+					worker.handle_undeliverable(mail, reason)
+				},
+				Ok(outcome) => {
+					worker.trace_mail(mail, outcome);
+					Ok(())
+				},
+				Err(err) => Err(err)
+			};
+			if let Err(err) = result {
+				return (inboxes, deliveries, Some((index, err)));
+			}
+			deliveries.push(worker.take_delivery(index));
+		}
+		(inboxes, deliveries, None)
+	}
+
+	/// Delivers all pending mail. Every user's mail is handled in the order it
+	/// was sent, but different users may handle theirs at the same time.
+	fn deliver_pending(&mut self) -> Result<(), RuntimeError> {
+		let mut deliveries = Vec::new();
+		let mut inboxes: Vec<Inbox> = Vec::new();
+		let mut error: Option<(usize, RuntimeError)> = None;
+		let pending = self.pending.split_off(0);
+		self.delivered += pending.len();
+		let skip = pending.len().saturating_sub(RECENT_MAILS);
+		self.recent.extend(pending[skip..].iter().cloned());
+		let skip = self.recent.len().saturating_sub(RECENT_MAILS);
+		self.recent.drain(..skip);
+		for (index, mail) in pending.into_iter().enumerate() {
+			let mut rejected = None;
+			let reason = match self.servers.get_mut(&mail.to.1) {
+				None => Some(BounceReason::NoServer),
+				Some(ref mut serv) => {
+					// The spam filter sees mail before the user does
+					if !serv.filter.check(&mail) {
+						rejected = Some(serv.filter.action);
+						None
+					} else if let Some(inbox) = inboxes.iter_mut()
+						.find(|v|v.server == mail.to.1 && v.name == mail.to.0) {
+						inbox.mail.push((index, mail));
+						continue;
+					} else {
+						match serv.users.remove(&mail.to.0) {
+							Some(user) => {
+								inboxes.push(Inbox {
+									server: mail.to.1.clone(),
+									name: mail.to.0.clone(),
+									user: user,
+									mail: vec![(index, mail)]
+								});
+								continue;
+							},
+							None => Some(BounceReason::NoUser)
+						}
+					}
+				}
+			};
+			let mut worker = self.create_worker();
+			if let Some(action) = rejected {
+				worker.trace_mail(&mail, Outcome::Undelivered(BounceReason::Rejected));
+				worker.handle_rejected(&mail, action);
+			}
+			if let Some(reason) = reason {
+				worker.trace_mail(&mail, Outcome::Undelivered(reason));
+				// Users were already taken out of their servers, so they must
+				// be put back before returning the error
+				if let Err(err) = worker.handle_undeliverable(&mail, reason) {
+					error = Some((index, err));
+				}
+			}
+			deliveries.push(worker.take_delivery(index));
+			if error.is_some() {
+				break;
+			}
+		}
+
+		// Split the inboxes between the threads, and deliver on this thread
+		// if there is only one. Only then is all mail delivered in the order
+		// it was sent, so otherwise output is held until it is merged.
+		let threads = ::std::cmp::max(1, ::std::cmp::min(self.threads, inboxes.len()));
+		let mut groups = (0..threads).map(|_|Vec::new()).collect::<Vec<Vec<Inbox>>>();
+		for (i, inbox) in inboxes.into_iter().enumerate() {
+			groups[i % threads].push(inbox);
+		}
+		let results = if threads == 1 {
+			vec![Interpreter::deliver(self.create_worker(), groups.pop().unwrap())]
+		} else {
+			let workers = groups.iter().map(|_| {
+				let mut worker = self.create_worker();
+				worker.buffer_output();
+				worker
+			}).collect::<Vec<Interpreter>>();
+			thread::scope(|scope| {
+				let handles = workers.into_iter().zip(groups.into_iter())
+					.map(|(worker, group)|scope.spawn(move||Interpreter::deliver(worker, group)))
+					.collect::<Vec<_>>();
+				handles.into_iter().map(|v|v.join().unwrap()).collect::<Vec<_>>()
+			})
 		};
 
-		// Just a note that theoretically this should be safe
-		user.send(selfhack/*huehuehue*/, &mail);
+		// Put the users back, and merge in the order the mail was sent
+		for (inboxes, mut delivered, err) in results {
+			for inbox in inboxes {
+				if let Some(serv) = self.servers.get_mut(&inbox.server) {
+					serv.add_user(inbox.name, inbox.user);
+				}
+			}
+			deliveries.append(&mut delivered);
+			if let Some(err) = err {
+				if error.as_ref().map(|v|err.0 < v.0).unwrap_or(true) {
+					error = Some(err);
+				}
+			}
+		}
+		deliveries.sort_by_key(|v|v.index);
+		for mut delivery in deliveries {
+			// Anything after an error would not have been delivered, so what
+			// other threads delivered past it is dropped
+			if error.as_ref().map(|v|delivery.index > v.0).unwrap_or(false) {
+				break;
+			}
+			if delivery.output.len() > 0 {
+				let mut out = self.get_output();
+				let _ = out.write_all(&delivery.output);
+				let _ = out.flush();
+			}
+			if let Some(ref tracer) = self.tracer {
+				for &(ref mail, ref outcome) in &delivery.traced {
+					tracer.mail(self.frame, mail, outcome);
+				}
+			}
+			self.pending.append(&mut delivery.pending);
+			self.users_to_add.append(&mut delivery.users_to_add);
+			self.servers_to_add.append(&mut delivery.servers_to_add);
+			self.failures.append(&mut delivery.failures);
+		}
+		match error {
+			Some((_, err)) => Err(err),
+			None => Ok(())
+		}
 	}
 
-	pub fn handle_pending(&mut self) -> bool {
+	pub fn handle_pending(&mut self) -> Result<bool, RuntimeError> {
 		if self.servers_to_add.len() == 0 &&
 		   self.users_to_add.len() == 0 &&
 		   self.pending.len() == 0 {
-			return false;
+			return Ok(false);
 		}
 
-		for server_name in self.servers_to_add.drain(..) {
-			self.servers.insert(server_name, Server{
-				users: HashMap::new()
-			});
+		// Only frames that deliver mail are counted
+		if self.pending.len() > 0 {
+			self.frame += 1;
+			if let Some(ref tracer) = self.tracer {
+				tracer.frame(self.frame);
+			}
+		}
+		for serv in self.servers.values_mut() {
+			serv.filter.new_frame();
+		}
+		for (server_name, filter) in self.servers_to_add.split_off(0) {
+			// Redefining a server removes its users
+			Arc::make_mut(&mut self.userdefs).retain(|k, _|k.1 != server_name);
+			self.servers.insert(server_name, Server::new(filter));
 		}
 		let users = self.users_to_add.split_off(0);
 		for def in users {
-			let mut serv = self.get_server(&def.1).unwrap();
+			Arc::make_mut(&mut self.userdefs).insert((def.0.clone(), def.1.clone()),
+				def.2.get_userdef());
+			let serv = try!(self.get_server_mut(&def.1).ok_or(
+				RuntimeError::new(RuntimeErrorType::UndefinedServer(def.1.clone()))));
 			let name = def.0.to_string();
 			serv.add_user(name, def.2);
 		}
-		let mail = self.pending.split_off(0);
-		for m in mail {
-			self.handle_sent_mail(&m);
-		}
+		try!(self.deliver_pending());
 
-		return true;
+		return Ok(true);
 	}
 
-	pub fn run(&mut self, instructions: &Vec<Instruction>, from: &UserPath, env: &mut Environment) {
+	pub fn run(&mut self, instructions: &Vec<Instruction>, from: &UserPath,
+	           env: &mut Environment) -> Result<(), RuntimeError> {
 		let mut i = 0;
 		loop {
 			if i >= instructions.len() {
@@ -103,14 +585,61 @@ impl Interpreter {
 			}
 			let inst = &instructions[i];
 			i = i + 1;
-			inst.call(self, from, env);
+			try!(inst.call(self, from, env));
+			if self.fallthrough {
+				break;
+			}
 		}
+		Ok(())
 	}
 
-	pub fn execute(&mut self, instructions: &Vec<Instruction>) {
-		self.handle_pending();
+	/// Returns an error if another frame would exceed a limit, given the
+	/// frame, number of delivered mails and time that execution started at.
+	fn check_limits(&self, start: (usize, usize, Instant)) -> Result<(), RuntimeError> {
+		let (frame, delivered, started) = start;
+		let exceeded = if self.limits.frames.map(|v|self.frame - frame >= v).unwrap_or(false) {
+			Limit::Frames(self.limits.frames.unwrap())
+		} else if self.limits.mails.map(|v|self.delivered - delivered + self.pending.len() > v)
+		                          .unwrap_or(false) {
+			Limit::Mails(self.limits.mails.unwrap())
+		} else if self.limits.pending.map(|v|self.pending.len() > v).unwrap_or(false) {
+			Limit::Pending(self.limits.pending.unwrap())
+		} else if self.limits.timeout.map(|v|started.elapsed() > v).unwrap_or(false) {
+			Limit::Timeout(self.limits.timeout.unwrap())
+		} else {
+			return Ok(());
+		};
+		Err(RuntimeError::new(RuntimeErrorType::LimitExceeded(exceeded, self.recent.clone())))
+	}
+
+	pub fn execute(&mut self, instructions: &Vec<Instruction>) -> Result<(), RuntimeError> {
 		let mut env = Environment::new();
-		self.run(instructions, &UserPath::new_anon(), &mut env);
-		while self.handle_pending() {}
+		self.execute_env(instructions, &mut env)
+	}
+
+	/// Executes the main block with the given environment, until there is no
+	/// more mail to deliver.
+	pub fn execute_env(&mut self, instructions: &Vec<Instruction>,
+	                   env: &mut Environment) -> Result<(), RuntimeError> {
+		try!(self.handle_pending());
+		try!(self.run(instructions, &UserPath::new_anon(), env));
+		let start = (self.frame, self.delivered, Instant::now());
+		loop {
+			if self.pending.len() > 0 {
+				if let Err(err) = self.check_limits(start) {
+					// Don't pick up where this left off if there is another execution
+					self.pending.clear();
+					return Err(err);
+				}
+			}
+			if !try!(self.handle_pending()) {
+				break;
+			}
+		}
+		if self.failures.len() > 0 {
+			let failures = self.failures.split_off(0);
+			return Err(RuntimeError::new(RuntimeErrorType::AssertionsFailed(failures)));
+		}
+		Ok(())
 	}
 }
@@ -0,0 +1,318 @@
+use num_bigint::{BigInt, Sign};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// How many digits past the operands' own are kept when dividing decimals.
+const DIVISION_DIGITS: u32 = 20;
+
+/// The largest exponent accepted when parsing a number such as `1e5`, and the
+/// most decimal places a number can be formatted with.
+pub const MAX_EXPONENT: u32 = 100000;
+
+/// The most bits a power may have, so that huge powers fail instead of
+/// running out of memory.
+const MAX_POWER_BITS: u64 = 1 << 24;
+
+/// How a number is rounded to fewer decimal places.
+#[derive(Clone, Copy)]
+pub enum Rounding {
+	/// To the nearest value, with halves rounded away from zero.
+	Nearest,
+	/// Towards negative infinity.
+	Floor,
+	/// Towards positive infinity.
+	Ceil,
+	/// Towards zero.
+	Trunc
+}
+
+/// An arbitrary-precision number, stored as an integer mantissa divided by
+/// `10^scale`. Integers have a scale of 0, and decimals are anything written
+/// with a decimal point or an exponent.
+#[derive(Clone, Debug)]
+pub struct Decimal {
+	mantissa: BigInt,
+	scale: u32
+}
+
+fn pow10(exp: u32) -> BigInt {
+	BigInt::from(10u32).pow(exp)
+}
+
+impl Decimal {
+	pub fn zero() -> Decimal {
+		Decimal::from_int(0)
+	}
+
+	pub fn from_int(val: i64) -> Decimal {
+		Decimal {
+			mantissa: BigInt::from(val),
+			scale: 0
+		}
+	}
+
+	/// Parses integers such as `-12`, and decimals such as `0.2` or `1e-5`.
+	pub fn parse(s: &str) -> Option<Decimal> {
+		let (num, exp) = match s.find(|c|c == 'e' || c == 'E') {
+			Some(i) => (&s[..i], Some(&s[i+1..])),
+			None => (s, None)
+		};
+		let (int, frac) = match num.find('.') {
+			Some(i) => (&num[..i], Some(&num[i+1..])),
+			None => (num, None)
+		};
+		let (negative, int) = match int.chars().next() {
+			Some('-') => (true, &int[1..]),
+			Some('+') => (false, &int[1..]),
+			_ => (false, int)
+		};
+		let frac_digits = frac.unwrap_or("");
+		if int.len() + frac_digits.len() == 0 ||
+			!int.chars().chain(frac_digits.chars()).all(|c|c.is_digit(10)) {
+			return None;
+		}
+		let mut mantissa = match BigInt::parse_bytes(format!("{}{}", int, frac_digits).as_bytes(), 10) {
+			Some(val) => val,
+			None => return None
+		};
+		if negative {
+			mantissa = -mantissa;
+		}
+		let mut scale = frac_digits.len() as i64;
+		let is_decimal = frac.is_some() || exp.is_some();
+		if let Some(exp) = exp {
+			let exp = match exp.parse::<i64>() {
+				Ok(val) if val.abs() <= MAX_EXPONENT as i64 => val,
+				_ => return None
+			};
+			scale -= exp;
+		}
+		if scale < 0 {
+			mantissa = mantissa * pow10((-scale) as u32);
+			scale = 0;
+		}
+		// Decimals keep at least one decimal place, so that they stay decimals
+		if is_decimal && scale == 0 {
+			mantissa = mantissa * pow10(1);
+			scale = 1;
+		}
+		Some(Decimal {
+			mantissa: mantissa,
+			scale: scale as u32
+		})
+	}
+
+	pub fn is_integer(&self) -> bool {
+		self.scale == 0
+	}
+
+	pub fn is_zero(&self) -> bool {
+		self.mantissa.sign() == Sign::NoSign
+	}
+
+	pub fn is_negative(&self) -> bool {
+		self.mantissa.sign() == Sign::Minus
+	}
+
+	/// Returns the mantissa as if the scale were the given one, which may not
+	/// be smaller than this number's.
+	fn mantissa_at(&self, scale: u32) -> BigInt {
+		&self.mantissa * pow10(scale - self.scale)
+	}
+
+	/// Returns both mantissas at the larger of both scales, and that scale.
+	fn align(&self, other: &Decimal) -> (BigInt, BigInt, u32) {
+		let scale = self.scale.max(other.scale);
+		(self.mantissa_at(scale), other.mantissa_at(scale), scale)
+	}
+
+	/// Removes trailing zeros past the given scale.
+	fn normalize(mut self, min_scale: u32) -> Decimal {
+		let ten = BigInt::from(10u32);
+		while self.scale > min_scale && (&self.mantissa % &ten).sign() == Sign::NoSign {
+			self.mantissa = self.mantissa / &ten;
+			self.scale -= 1;
+		}
+		self
+	}
+
+	pub fn add(&self, other: &Decimal) -> Decimal {
+		let (a, b, scale) = self.align(other);
+		Decimal {
+			mantissa: a + b,
+			scale: scale
+		}
+	}
+
+	pub fn sub(&self, other: &Decimal) -> Decimal {
+		self.add(&other.neg())
+	}
+
+	pub fn mul(&self, other: &Decimal) -> Decimal {
+		Decimal {
+			mantissa: &self.mantissa * &other.mantissa,
+			scale: self.scale + other.scale
+		}.normalize(self.scale.max(other.scale))
+	}
+
+	/// Divides two numbers, or returns None when dividing by zero. Integers
+	/// are divided towards zero, and decimals to `DIVISION_DIGITS` more
+	/// places than either has.
+	pub fn div(&self, other: &Decimal) -> Option<Decimal> {
+		if other.is_zero() {
+			return None;
+		}
+		if self.is_integer() && other.is_integer() {
+			return Some(Decimal {
+				mantissa: &self.mantissa / &other.mantissa,
+				scale: 0
+			});
+		}
+		let min_scale = self.scale.max(other.scale);
+		let scale = min_scale + DIVISION_DIGITS;
+		let num = &self.mantissa * pow10(other.scale + scale - self.scale);
+		Some(Decimal {
+			mantissa: num / &other.mantissa,
+			scale: scale
+		}.normalize(min_scale))
+	}
+
+	/// The remainder of dividing two numbers, with the sign of the first, or
+	/// None when dividing by zero.
+	pub fn rem(&self, other: &Decimal) -> Option<Decimal> {
+		if other.is_zero() {
+			return None;
+		}
+		let (a, b, scale) = self.align(other);
+		Some(Decimal {
+			mantissa: a % b,
+			scale: scale
+		})
+	}
+
+	pub fn neg(&self) -> Decimal {
+		Decimal {
+			mantissa: -&self.mantissa,
+			scale: self.scale
+		}
+	}
+
+	pub fn abs(&self) -> Decimal {
+		if self.is_negative() { self.neg() } else { self.clone() }
+	}
+
+	/// Raises this number to a power, or returns None if the result would be
+	/// too large, or have more than `MAX_EXPONENT` decimal places.
+	pub fn pow(&self, exp: u32) -> Option<Decimal> {
+		let base = self.clone().normalize(0);
+		// Powers of -1, 0 and 1 are never large, however large the exponent
+		if base.mantissa.bits() > 1 && base.mantissa.bits().saturating_mul(exp as u64) > MAX_POWER_BITS {
+			return None;
+		}
+		let scale = match base.scale.checked_mul(exp) {
+			Some(val) if val <= MAX_EXPONENT => val,
+			_ => return None
+		};
+		let ret = Decimal {
+			mantissa: base.mantissa.pow(exp),
+			scale: scale
+		};
+		// Decimals stay decimals, e.g. 1.0 to any power is 1.0
+		Some(if ret.scale < self.scale {
+			Decimal {
+				mantissa: ret.mantissa_at(self.scale),
+				scale: self.scale
+			}
+		} else {
+			ret
+		})
+	}
+
+	/// Rounds this number to at most the given number of decimal places.
+	pub fn round(&self, places: u32, rounding: Rounding) -> Decimal {
+		if self.scale <= places {
+			return self.clone();
+		}
+		let divisor = pow10(self.scale - places);
+		let mut quotient = &self.mantissa / &divisor;
+		let remainder = &self.mantissa % &divisor;
+		let one = BigInt::from(1u32);
+		match (rounding, remainder.sign()) {
+			(Rounding::Floor, Sign::Minus) => quotient = quotient - one,
+			(Rounding::Ceil, Sign::Plus) => quotient = quotient + one,
+			(Rounding::Nearest, sign) if remainder.magnitude() * 2u32 >= *divisor.magnitude() => {
+				if sign == Sign::Minus {
+					quotient = quotient - one;
+				} else {
+					quotient = quotient + one;
+				}
+			},
+			_ => {}
+		}
+		Decimal {
+			mantissa: quotient,
+			scale: places
+		}
+	}
+
+	/// Rounds this number to the nearest value with exactly the given number
+	/// of decimal places, padding it with zeros if it has fewer. Returns None
+	/// if there are more than `MAX_EXPONENT` places.
+	pub fn fixed(&self, places: u32) -> Option<Decimal> {
+		if places > MAX_EXPONENT {
+			return None;
+		}
+		let rounded = self.round(places, Rounding::Nearest);
+		Some(Decimal {
+			mantissa: rounded.mantissa_at(places),
+			scale: places
+		})
+	}
+
+	/// Returns this number as a u32, if it is a whole number that fits.
+	pub fn to_u32(&self) -> Option<u32> {
+		if self.round(0, Rounding::Trunc).cmp(self) != Ordering::Equal {
+			return None;
+		}
+		(&self.mantissa / pow10(self.scale)).to_string().parse::<u32>().ok()
+	}
+}
+
+impl PartialEq for Decimal {
+	fn eq(&self, other: &Decimal) -> bool {
+		self.cmp(other) == Ordering::Equal
+	}
+}
+
+impl Eq for Decimal {}
+
+impl PartialOrd for Decimal {
+	fn partial_cmp(&self, other: &Decimal) -> Option<Ordering> {
+		Some(self.cmp(other))
+	}
+}
+
+impl Ord for Decimal {
+	fn cmp(&self, other: &Decimal) -> Ordering {
+		let (a, b, _) = self.align(other);
+		a.cmp(&b)
+	}
+}
+
+impl fmt::Display for Decimal {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let digits = self.mantissa.magnitude().to_string();
+		let sign = if self.is_negative() { "-" } else { "" };
+		if self.scale == 0 {
+			return write!(f, "{}{}", sign, digits);
+		}
+		let scale = self.scale as usize;
+		let digits = if digits.len() <= scale {
+			format!("{}{}", "0".repeat(scale + 1 - digits.len()), digits)
+		} else {
+			digits
+		};
+		let (int, frac) = digits.split_at(digits.len() - scale);
+		write!(f, "{}{}.{}", sign, int, frac)
+	}
+}
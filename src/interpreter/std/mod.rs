@@ -1,8 +1,12 @@
 use interpreter::Interpreter;
+mod decimal;
+mod stdassert;
 mod stdcmp;
 mod stdio;
 mod stdloop;
+mod stdmailer;
 mod stdmath;
+mod stdstr;
 
 pub fn create_std_lib(inter: &mut Interpreter) {
 	inter.add_server("std.com");
@@ -19,5 +23,14 @@ pub fn create_std_lib(inter: &mut Interpreter) {
 	// Math
 	inter.add_user("math", "std.com", &stdmath::create());
 
-	inter.handle_pending();
+	// Strings
+	inter.add_user("str", "std.com", &stdstr::create());
+
+	// Assertions
+	inter.add_user("assert", "std.com", &stdassert::create());
+
+	// Bounced mail
+	inter.add_user("mailer-daemon", "std.com", &stdmailer::create());
+
+	inter.handle_pending().expect("Could not create the standard library!");
 }
@@ -0,0 +1,59 @@
+use user::*;
+use interpreter::Interpreter;
+use mail::Mail;
+use regex::Regex;
+
+fn get_bool(val: Option<&String>) -> bool {
+	match val {
+		Some(s) => !["false", "0", ""].contains(&s.to_lowercase().as_str()),
+		None => false
+	}
+}
+
+fn show(val: Option<&String>) -> String {
+	match val {
+		Some(s) => format!("{:?}", s),
+		None => "nothing".to_string()
+	}
+}
+
+fn func(_: &User, inter: &mut Interpreter, mail: &Mail) {
+	let a = mail.attachments.get(0);
+	let b = mail.attachments.get(1);
+	let failure = match mail.subject.as_ref() {
+		"eq" => match a == b {
+			true => None,
+			false => Some(format!("expected {} to equal {}", show(a), show(b)))
+		},
+		"neq" => match a != b {
+			true => None,
+			false => Some(format!("expected {} to not equal {}", show(a), show(b)))
+		},
+		"match" => {
+			let text = a.map(|v|v.as_str()).unwrap_or("");
+			let pattern = b.map(|v|v.as_str()).unwrap_or("");
+			match Regex::new(pattern) {
+				Ok(ref r) if r.is_match(text) => None,
+				Ok(_) => Some(format!("expected {:?} to match {:?}", text, pattern)),
+				Err(_) => Some(format!("invalid regex {:?}", pattern))
+			}
+		},
+		"true" => match get_bool(a) {
+			true => None,
+			false => Some(format!("expected {} to be true", show(a)))
+		},
+		"fail" => Some("failed".to_string()),
+		o => Some(format!("unknown assertion {:?}", o))
+	};
+	if let Some(reason) = failure {
+		let message = match mail.message.len() {
+			0 => reason,
+			_ => format!("{} ({})", mail.message, reason)
+		};
+		inter.fail_assertion(&mail.from, &message);
+	}
+}
+
+pub fn create() -> UserDef {
+	UserDef::create_def_external(Box::new(func))
+}
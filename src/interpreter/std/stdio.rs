@@ -1,28 +1,29 @@
 use user::*;
 use interpreter::Interpreter;
 use mail::Mail;
-use std::io::{self, BufRead, Write};
+use std::io::{BufRead, Write};
 
 fn func(_: &User, inter: &mut Interpreter, mail: &Mail) {
 	match mail.subject.as_ref() {
 		"print" => {
-			print!("{}", mail.message);
+			let mut out = inter.get_output();
+			let _ = write!(out, "{}", mail.message);
 			for val in &mail.attachments {
-				print!(" {}", val);
+				let _ = write!(out, " {}", val);
 			}
-			io::stdout().flush().unwrap();
+			let _ = out.flush();
 		},
 		"println" => {
-			print!("{}", mail.message);
+			let mut out = inter.get_output();
+			let _ = write!(out, "{}", mail.message);
 			for val in &mail.attachments {
-				print!(" {}", val);
+				let _ = write!(out, " {}", val);
 			}
-			print!("\n");
+			let _ = write!(out, "\n");
 		},
 		"input" => {
 			let mut line = String::new();
-			let stdin = io::stdin();
-			stdin.lock().read_line(&mut line).unwrap();
+			let _ = inter.get_input().read_line(&mut line);
 			mail.return_mail(inter, &mail.message, line.trim_right(), Vec::new());
 		},
 		o => println!("Bad io function {}!", o)
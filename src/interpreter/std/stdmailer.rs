@@ -0,0 +1,10 @@
+use user::*;
+use interpreter::Interpreter;
+use mail::Mail;
+
+// The mailer daemon only sends bounced mail; anything sent to it is discarded.
+fn func(_: &User, _: &mut Interpreter, _: &Mail) {}
+
+pub fn create() -> UserDef {
+	UserDef::create_def_external(Box::new(func))
+}
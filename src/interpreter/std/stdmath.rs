@@ -2,80 +2,150 @@ use user::*;
 use interpreter::Interpreter;
 use mail::Mail;
 use std::char;
+use std::fmt;
+use super::decimal::{Decimal, Rounding, MAX_EXPONENT};
+
+/// Why a math function could not give a result.
+enum MathError {
+	NotANumber(String),
+	/// A number that must be a non-negative whole number, such as an
+	/// exponent or a number of decimal places, is not.
+	NotWhole(String),
+	DivideByZero,
+	Overflow,
+	/// More decimal places are asked for than `MAX_EXPONENT`.
+	TooManyPlaces,
+	/// The function takes exactly this many numbers.
+	Operands(usize),
+	/// The function takes one or two numbers.
+	OneOrTwo,
+	NoOperands
+}
+
+impl fmt::Display for MathError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			MathError::NotANumber(ref s) => write!(f, "'{}' is not a number", s),
+			MathError::NotWhole(ref s) => write!(f, "'{}' is not a non-negative whole number", s),
+			MathError::DivideByZero => write!(f, "division by zero"),
+			MathError::Overflow => write!(f, "overflow"),
+			MathError::TooManyPlaces => write!(f, "too many decimal places (max {})", MAX_EXPONENT),
+			MathError::Operands(n) => write!(f, "expected {} number{}", n, if n == 1 { "" } else { "s" }),
+			MathError::OneOrTwo => write!(f, "expected 1 or 2 numbers"),
+			MathError::NoOperands => write!(f, "expected at least one number")
+		}
+	}
+}
+
+fn parse_all(attachments: &[String]) -> Result<Vec<Decimal>, MathError> {
+	attachments
+		.iter()
+		.map(|v|Decimal::parse(v).ok_or(MathError::NotANumber(v.clone())))
+		.collect()
+}
+
+/// Parses exactly `count` numbers.
+fn parse_exact(attachments: &[String], count: usize) -> Result<Vec<Decimal>, MathError> {
+	let nums = try!(parse_all(attachments));
+	if nums.len() != count {
+		return Err(MathError::Operands(count));
+	}
+	Ok(nums)
+}
+
+fn to_u32(num: &Decimal) -> Result<u32, MathError> {
+	num.to_u32().ok_or(MathError::NotWhole(num.to_string()))
+}
+
+/// Folds every number after the first into the first, which is 0 if there
+/// are no numbers. Fails on division by zero.
+fn fold_first<F>(nums: &[Decimal], op: F) -> Result<Decimal, MathError>
+	where F: Fn(&Decimal, &Decimal) -> Option<Decimal> {
+	match nums.split_first() {
+		Some((first, rest)) => rest.iter().fold(Ok(first.clone()), |acc, x| {
+			acc.and_then(|acc|op(&acc, x).ok_or(MathError::DivideByZero))
+		}),
+		None => Ok(Decimal::zero())
+	}
+}
+
+fn compare<F>(attachments: &[String], op: F) -> Result<String, MathError>
+	where F: Fn(&Decimal, &Decimal) -> bool {
+	let nums = try!(parse_exact(attachments, 2));
+	Ok(op(&nums[0], &nums[1]).to_string())
+}
+
+/// Rounds a number to the number of decimal places given after it, or 0.
+fn round(attachments: &[String], rounding: Rounding) -> Result<Decimal, MathError> {
+	let nums = try!(parse_all(attachments));
+	match nums.len() {
+		1 => Ok(nums[0].round(0, rounding)),
+		2 => Ok(nums[0].round(try!(to_u32(&nums[1])), rounding)),
+		_ => Err(MathError::OneOrTwo)
+	}
+}
+
+/// Runs an arithmetic function, or returns None if there is no such function.
+fn calculate(function: &str, attachments: &[String]) -> Option<Result<Decimal, MathError>> {
+	let nums = || parse_all(attachments);
+	Some(match function {
+		"add" => nums().map(|v|v.iter().fold(Decimal::zero(), |acc, x|acc.add(x))),
+		"sub" => nums().map(|v|match v.split_first() {
+			Some((first, rest)) => rest.iter().fold(first.clone(), |acc, x|acc.sub(x)),
+			None => Decimal::zero()
+		}),
+		"mul" => nums().map(|v|v.iter().fold(Decimal::from_int(1), |acc, x|acc.mul(x))),
+		"div" => nums().and_then(|v|fold_first(&v, Decimal::div)),
+		"mod" => nums().and_then(|v|fold_first(&v, Decimal::rem)),
+		"neg" => parse_exact(attachments, 1).map(|v|v[0].neg()),
+		"abs" => parse_exact(attachments, 1).map(|v|v[0].abs()),
+		"pow" => parse_exact(attachments, 2).and_then(|v| {
+			let exp = try!(to_u32(&v[1]));
+			v[0].pow(exp).ok_or(MathError::Overflow)
+		}),
+		"min" => nums().and_then(|v|v.into_iter().min().ok_or(MathError::NoOperands)),
+		"max" => nums().and_then(|v|v.into_iter().max().ok_or(MathError::NoOperands)),
+		"round" => round(attachments, Rounding::Nearest),
+		"floor" => round(attachments, Rounding::Floor),
+		"ceil" => round(attachments, Rounding::Ceil),
+		"trunc" => round(attachments, Rounding::Trunc),
+		"fixed" => parse_exact(attachments, 2).and_then(|v| {
+			let places = try!(to_u32(&v[1]));
+			v[0].fixed(places).ok_or(MathError::TooManyPlaces)
+		}),
+		_ => return None
+	})
+}
+
+/// Runs a comparison, or returns None if there is no such comparison.
+fn calculate_cmp(function: &str, attachments: &[String]) -> Option<Result<String, MathError>> {
+	Some(match function {
+		"lt" => compare(attachments, |a, b|a < b),
+		"le" => compare(attachments, |a, b|a <= b),
+		"gt" => compare(attachments, |a, b|a > b),
+		"ge" => compare(attachments, |a, b|a >= b),
+		_ => return None
+	})
+}
 
 fn func(_: &User, inter: &mut Interpreter, mail: &Mail) {
-	match mail.subject.as_ref() {
-		"add" => {
-			let mut is_okay = true;
-			let sum = mail.attachments
-				.iter()
-				.filter_map(|v| {
-					match v.parse::<i64>() {
-						Ok(val) => Some(val),
-						_ => {
-							is_okay = false;
-							None
-						}
-					}
-				})
-				.fold(0, |acc, x| acc + x);
-			if is_okay {
-				inter.mail(Mail::create(mail.to.clone(), mail.from.clone(), &mail.message, &sum.to_string()));
-			} else {
-				inter.mail(Mail::create(mail.to.clone(), mail.from.clone(), &mail.message, ""));
+	let result = calculate(&mail.subject, &mail.attachments)
+		.map(|v|v.map(|v|v.to_string()))
+		.or_else(||calculate_cmp(&mail.subject, &mail.attachments));
+	if let Some(result) = result {
+		// Errors are replied with no content, and the reason attached
+		let retmail = match result {
+			Ok(val) => Mail::create(mail.to.clone(), mail.from.clone(), &mail.message, &val),
+			Err(err) => {
+				let mut retmail = Mail::create(mail.to.clone(), mail.from.clone(), &mail.message, "");
+				retmail.attach(&err.to_string());
+				retmail
 			}
-		},
-		"mul" => {
-			let mut is_okay = true;
-			let sum = mail.attachments
-				.iter()
-				.filter_map(|v| {
-					match v.parse::<i64>() {
-						Ok(val) => Some(val),
-						_ => {
-							is_okay = false;
-							None
-						}
-					}
-				})
-				.fold(1, |acc, x| acc * x);
-			if is_okay {
-				inter.mail(Mail::create(mail.to.clone(), mail.from.clone(), &mail.message, &sum.to_string()));
-			} else {
-				inter.mail(Mail::create(mail.to.clone(), mail.from.clone(), &mail.message, ""));
-			}
-		},
-		"div" => {
-			let mut is_okay = true;
-			let base = mail.attachments
-				.get(0)
-				.map(|v| v.parse::<i64>().ok())
-				.unwrap_or(Some(0));
-			let base = match base {
-				Some(val) => val,
-				None => {
-					is_okay = false;
-					0
-				}
-			};
-			let sum = mail.attachments[1..]
-				.iter()
-				.filter_map(|v| {
-					match v.parse::<i64>() {
-						Ok(val) => Some(val),
-						_ => {
-							is_okay = false;
-							None
-						}
-					}
-				})
-				.fold(base, |acc, x| acc / x);
-			if is_okay {
-				inter.mail(Mail::create(mail.to.clone(), mail.from.clone(), &mail.message, &sum.to_string()));
-			} else {
-				inter.mail(Mail::create(mail.to.clone(), mail.from.clone(), &mail.message, ""));
-			}
-		},
+		};
+		inter.mail(retmail);
+		return;
+	}
+	match mail.subject.as_ref() {
 		"ord" => {
 			let ords = mail.attachments
 				.iter()
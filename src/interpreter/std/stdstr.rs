@@ -0,0 +1,199 @@
+use user::*;
+use interpreter::Interpreter;
+use mail::Mail;
+use std::fmt;
+
+/// The longest text that `repeat` and `pad` will create.
+const MAX_LENGTH: usize = 1 << 24;
+
+/// Why a string function could not give a result.
+enum StrError {
+	/// The function takes exactly this many values.
+	Operands(usize),
+	/// The function takes between these many values.
+	OperandRange(usize, usize),
+	NoOperands,
+	/// A count or width is not a whole number.
+	NotANumber(String),
+	/// A fill is not a single character.
+	BadFill(String),
+	/// A format template refers to a value that isn't given.
+	MissingValue(usize),
+	/// A format template has a brace that isn't part of a valid placeholder.
+	BadTemplate,
+	TooLong
+}
+
+impl fmt::Display for StrError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			StrError::Operands(n) => write!(f, "expected {} value{}", n, if n == 1 { "" } else { "s" }),
+			StrError::OperandRange(a, b) => write!(f, "expected {} to {} values", a, b),
+			StrError::NoOperands => write!(f, "expected at least one value"),
+			StrError::NotANumber(ref s) => write!(f, "'{}' is not a whole number", s),
+			StrError::BadFill(ref s) => write!(f, "'{}' is not a single character", s),
+			StrError::MissingValue(i) => write!(f, "no value for placeholder {}", i),
+			StrError::BadTemplate => write!(f, "bad brace or placeholder in template"),
+			StrError::TooLong => write!(f, "result is too long")
+		}
+	}
+}
+
+/// Checks that exactly `count` values are given.
+fn exact(attachments: &[String], count: usize) -> Result<&[String], StrError> {
+	if attachments.len() != count {
+		return Err(StrError::Operands(count));
+	}
+	Ok(attachments)
+}
+
+fn parse_num<T: ::std::str::FromStr>(s: &str) -> Result<T, StrError> {
+	s.parse::<T>().map_err(|_|StrError::NotANumber(s.to_string()))
+}
+
+/// Returns the character index of the first occurrence of `needle`, or -1.
+fn find(haystack: &str, needle: &str) -> isize {
+	match haystack.find(needle) {
+		Some(i) => haystack[..i].chars().count() as isize,
+		None => -1
+	}
+}
+
+/// Returns the fill characters needed to pad text to the width, ignoring its
+/// sign.
+fn padding(text: &str, width: isize, fill: &str) -> Result<String, StrError> {
+	let mut chars = fill.chars();
+	let fill = match (chars.next(), chars.next()) {
+		(Some(c), None) => c,
+		_ => return Err(StrError::BadFill(fill.to_string()))
+	};
+	let width = match width.checked_abs() {
+		Some(val) if val as usize <= MAX_LENGTH => val as usize,
+		_ => return Err(StrError::TooLong)
+	};
+	Ok((0..width.saturating_sub(text.chars().count())).map(|_|fill).collect())
+}
+
+/// Fills a template's `{}` placeholders with values in order, and `{N}`
+/// placeholders with the Nth value. `{{` and `}}` are literal braces.
+fn format(template: &str, values: &[String]) -> Result<String, StrError> {
+	let mut ret = String::new();
+	let mut next = 0;
+	let mut chars = template.chars().peekable();
+	while let Some(c) = chars.next() {
+		match c {
+			'{' if chars.peek() == Some(&'{') => {
+				chars.next();
+				ret.push('{');
+			},
+			'}' if chars.peek() == Some(&'}') => {
+				chars.next();
+				ret.push('}');
+			},
+			'{' => {
+				let mut index = String::new();
+				loop {
+					match chars.next() {
+						Some('}') => break,
+						Some(c) if c.is_digit(10) => index.push(c),
+						_ => return Err(StrError::BadTemplate)
+					}
+				}
+				let index = if index.is_empty() {
+					next += 1;
+					next - 1
+				} else {
+					try!(parse_num::<usize>(&index))
+				};
+				match values.get(index) {
+					Some(val) => ret.push_str(val),
+					None => return Err(StrError::MissingValue(index))
+				}
+			},
+			'}' => return Err(StrError::BadTemplate),
+			c => ret.push(c)
+		}
+	}
+	Ok(ret)
+}
+
+/// A reply's content and attachments.
+type Reply = (String, Vec<String>);
+
+fn text(val: String) -> Reply {
+	(val, Vec::new())
+}
+
+/// Runs a string function, returning what to reply with, or None if there is
+/// no such function.
+fn calculate(function: &str, args: &[String]) -> Option<Result<Reply, StrError>> {
+	let one = |f: &dyn Fn(&str) -> String| exact(args, 1).map(|v|text(f(&v[0])));
+	Some(match function {
+		"len" => one(&|v|v.chars().count().to_string()),
+		"upper" => one(&|v|v.to_uppercase()),
+		"lower" => one(&|v|v.to_lowercase()),
+		"trim" => one(&|v|v.trim().to_string()),
+		"reverse" => one(&|v|v.chars().rev().collect()),
+		// The parts are attached, and the first is also the content
+		"split" => match args.len() {
+			1 => Ok(args[0].split_whitespace().map(|v|v.to_string()).collect()),
+			2 if args[1].is_empty() => Ok(args[0].chars().map(|v|v.to_string()).collect()),
+			2 => Ok(args[0].split(args[1].as_str()).map(|v|v.to_string()).collect()),
+			_ => Err(StrError::OperandRange(1, 2))
+		}.map(|parts: Vec<String>|(parts.get(0).cloned().unwrap_or(String::new()), parts)),
+		"join" => match args.split_first() {
+			Some((sep, parts)) => Ok(text(parts.join(sep))),
+			None => Err(StrError::NoOperands)
+		},
+		"replace" => exact(args, 3).map(|v|text(v[0].replace(v[1].as_str(), &v[2]))),
+		"find" => exact(args, 2).map(|v|text(find(&v[0], &v[1]).to_string())),
+		"startswith" => exact(args, 2).map(|v|text(v[0].starts_with(v[1].as_str()).to_string())),
+		"endswith" => exact(args, 2).map(|v|text(v[0].ends_with(v[1].as_str()).to_string())),
+		"repeat" => exact(args, 2).and_then(|v| {
+			let count = try!(parse_num::<usize>(&v[1]));
+			match v[0].len().checked_mul(count) {
+				Some(len) if len <= MAX_LENGTH => Ok(text(v[0].repeat(count))),
+				_ => Err(StrError::TooLong)
+			}
+		}),
+		"pad" => match args.len() {
+			2 | 3 => parse_num::<isize>(&args[1]).and_then(|width| {
+				let padding = try!(padding(&args[0], width, args.get(2).map(|v|v.as_str()).unwrap_or(" ")));
+				Ok(text(if width < 0 {
+					format!("{}{}", args[0], padding)
+				} else {
+					format!("{}{}", padding, args[0])
+				}))
+			}),
+			_ => Err(StrError::OperandRange(2, 3))
+		},
+		"format" => match args.split_first() {
+			Some((template, values)) => format(template, values).map(text),
+			None => Err(StrError::NoOperands)
+		},
+		_ => return None
+	})
+}
+
+fn func(_: &User, inter: &mut Interpreter, mail: &Mail) {
+	match calculate(&mail.subject, &mail.attachments) {
+		Some(Ok((content, attachments))) => {
+			let mut retmail = Mail::create(mail.to.clone(), mail.from.clone(), &mail.message, &content);
+			for val in &attachments {
+				retmail.attach(val);
+			}
+			inter.mail(retmail);
+		},
+		// Errors are replied with no content, and the reason attached
+		Some(Err(err)) => {
+			let mut retmail = Mail::create(mail.to.clone(), mail.from.clone(), &mail.message, "");
+			retmail.attach(&err.to_string());
+			inter.mail(retmail);
+		},
+		None => println!("Bad string function {}!", mail.subject)
+	}
+}
+
+pub fn create() -> UserDef {
+	UserDef::create_def_external(Box::new(func))
+}
@@ -0,0 +1,111 @@
+use mail::{Mail, BounceReason};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// How the trace is written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFormat {
+	/// One line per frame and mail, for reading.
+	Text,
+	/// One JSON object per line, for tooling.
+	Json
+}
+
+/// What happened to a traced mail.
+#[derive(Clone, Debug)]
+pub enum Outcome {
+	/// A handler with this regex accepted the mail.
+	Handler(String),
+	/// No handler matched, so the user's else block accepted the mail.
+	CatchAll,
+	/// A native user accepted the mail.
+	Native,
+	/// The mail could not be delivered.
+	Undelivered(BounceReason)
+}
+
+/// Logs every frame and every mail as it is delivered. Clones share the same
+/// output.
+#[derive(Clone)]
+pub struct Tracer {
+	format: TraceFormat,
+	output: Arc<Mutex<Box<dyn Write + Send>>>
+}
+
+/// Quotes a string for JSON.
+fn json_string(s: &str) -> String {
+	let mut ret = String::from("\"");
+	for c in s.chars() {
+		match c {
+			'"' => ret.push_str("\\\""),
+			'\\' => ret.push_str("\\\\"),
+			'\n' => ret.push_str("\\n"),
+			'\r' => ret.push_str("\\r"),
+			'\t' => ret.push_str("\\t"),
+			c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+			c => ret.push(c)
+		}
+	}
+	ret.push('"');
+	ret
+}
+
+impl Tracer {
+	pub fn new(format: TraceFormat, output: Box<dyn Write + Send>) -> Tracer {
+		Tracer {
+			format: format,
+			output: Arc::new(Mutex::new(output))
+		}
+	}
+
+	fn write_line(&self, line: &str) {
+		let mut out = self.output.lock().unwrap_or_else(|err|err.into_inner());
+		let _ = writeln!(out, "{}", line);
+	}
+
+	/// Logs the start of a frame.
+	pub fn frame(&self, frame: usize) {
+		self.write_line(&match self.format {
+			TraceFormat::Text => format!("--- frame {} ---", frame),
+			TraceFormat::Json => format!("{{\"event\":\"frame\",\"frame\":{}}}", frame)
+		});
+	}
+
+	/// Logs a delivered mail, and what happened to it.
+	pub fn mail(&self, frame: usize, mail: &Mail, outcome: &Outcome) {
+		self.write_line(&match self.format {
+			TraceFormat::Text => {
+				format!("{} {}", mail, match *outcome {
+					Outcome::Handler(ref re) => format!("matched {:?}", re),
+					Outcome::CatchAll => "caught by else".to_string(),
+					Outcome::Native => "native".to_string(),
+					Outcome::Undelivered(BounceReason::NoHandler) => "no match".to_string(),
+					Outcome::Undelivered(reason) => format!("not delivered, {}", reason)
+				})
+			},
+			TraceFormat::Json => {
+				let (handler, result) = match *outcome {
+					Outcome::Handler(ref re) => (json_string(re), "matched".to_string()),
+					Outcome::CatchAll => ("null".to_string(), "else".to_string()),
+					Outcome::Native => ("null".to_string(), "native".to_string()),
+					Outcome::Undelivered(reason) => ("null".to_string(), match reason {
+						BounceReason::NoServer => "no server",
+						BounceReason::NoUser => "no user",
+						BounceReason::NoHandler => "no match",
+						BounceReason::Rejected => "rejected"
+					}.to_string())
+				};
+				format!("{{\"event\":\"mail\",\"frame\":{},\"from\":{},\"to\":{},\"subject\":{},\
+				         \"message\":{},\"attachments\":[{}],\"handler\":{},\"result\":{}}}",
+					frame,
+					json_string(&format!("{:?}", mail.from)),
+					json_string(&format!("{:?}", mail.to)),
+					json_string(&mail.subject),
+					json_string(&mail.message),
+					mail.attachments.iter().map(|v|json_string(v)).collect::<Vec<String>>().join(","),
+					handler,
+					json_string(&result))
+			}
+		});
+	}
+}
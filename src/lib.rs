@@ -0,0 +1,47 @@
+//! Emailang, a programming language based on emails.
+//!
+//! The interpreter can be embedded in other programs. Native users and
+//! modifiers can be registered with the interpreter before running code, and
+//! `<io@std.com>` can write to any `Write` and read from any `BufRead`.
+//! `OutputBuffer` can be used to capture output in memory.
+//!
+//! ```
+//! use emailang::{Interpreter, Mail, OutputBuffer};
+//! use std::io::Write;
+//!
+//! let code = "(greet, \"World\") > <hello@host.com>;";
+//! let instructions = emailang::parse(code).ok().unwrap();
+//!
+//! let mut inter = Interpreter::new();
+//! inter.add_server("host.com");
+//! inter.add_native_user("hello", "host.com", |_, inter: &mut Interpreter, mail: &Mail| {
+//! 	let _ = write!(inter.get_output(), "Hello, {}!", mail.message);
+//! });
+//! let output = OutputBuffer::new();
+//! inter.set_output(Box::new(output.clone()));
+//! inter.execute(&instructions).ok().unwrap();
+//! assert_eq!(output.get_string(), "Hello, World!");
+//! ```
+pub mod types;
+pub mod symbols;
+pub mod interpreter;
+pub mod parser;
+pub mod instruction;
+pub mod user;
+pub mod server;
+pub mod mail;
+pub mod error;
+pub mod environment;
+pub mod modifier;
+pub mod analysis;
+extern crate regex;
+extern crate num_bigint;
+
+pub use interpreter::Interpreter;
+pub use interpreter::buffer::OutputBuffer;
+pub use parser::parse;
+pub use mail::Mail;
+pub use types::Type;
+pub use user::{User, UserDef, UserPath};
+pub use environment::Environment;
+pub use error::{SyntaxError, RuntimeError, RuntimeErrorType};
@@ -1,7 +1,8 @@
 use user::UserPath;
 use interpreter::Interpreter;
+use std::fmt;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct Mail {
 	pub subject: String,
 	pub message: String,
@@ -10,6 +11,16 @@ pub struct Mail {
 	pub attachments: Vec<String>
 }
 
+impl fmt::Display for Mail {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		try!(write!(f, "<{:?}> ({:?}, {:?}", self.from, self.subject, self.message));
+		for a in &self.attachments {
+			try!(write!(f, ", {:?}", a));
+		}
+		write!(f, ") > <{:?}>", self.to)
+	}
+}
+
 impl Mail {
 	pub fn attach(&mut self, val: &str) {
 		self.attachments.push(val.to_string())
@@ -42,3 +53,23 @@ pub struct Draft {
 	pub message: String,
 	pub attachments: Vec<String>
 }
+
+/// The reason a piece of mail could not be delivered.
+#[derive(Clone, Copy, Debug)]
+pub enum BounceReason {
+	NoServer,
+	NoUser,
+	NoHandler,
+	Rejected
+}
+
+impl fmt::Display for BounceReason {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			BounceReason::NoServer => write!(f, "server does not exist"),
+			BounceReason::NoUser => write!(f, "user does not exist"),
+			BounceReason::NoHandler => write!(f, "no handler matches the subject"),
+			BounceReason::Rejected => write!(f, "rejected by the server's spam filter"),
+		}
+	}
+}
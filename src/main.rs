@@ -1,23 +1,116 @@
-pub mod types;
-pub mod symbols;
-pub mod interpreter;
-pub mod parser;
-pub mod instruction;
-pub mod user;
-pub mod server;
-pub mod mail;
-pub mod error;
-pub mod environment;
-pub mod modifier;
-extern crate regex;
+extern crate emailang;
+mod repl;
+mod tester;
 
 use std::error::Error;
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::{self, BufReader};
 use std::path::Path;
 use std::env;
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+use emailang::{interpreter, parser, analysis};
+use emailang::error::SyntaxError;
+use emailang::analysis::Warning;
+use emailang::interpreter::{UndeliverableMode, Limits};
+use emailang::interpreter::trace::TraceFormat;
+use emailang::interpreter::graph::MailGraph;
 
-fn run(fname: &str) {
+/// Options given on the command line.
+#[derive(Clone)]
+struct Options {
+	undeliverable: UndeliverableMode,
+	input: Option<String>,
+	threads: usize,
+	trace: Option<TraceFormat>,
+	graph: Option<String>,
+	limits: Limits
+}
+
+impl Options {
+	fn new() -> Options {
+		Options {
+			undeliverable: UndeliverableMode::Drop,
+			input: None,
+			threads: 1,
+			trace: None,
+			graph: None,
+			limits: Limits::default()
+		}
+	}
+
+	/// Creates an interpreter configured with these options.
+	fn create_interpreter(&self) -> interpreter::Interpreter {
+		let mut inter = interpreter::Interpreter::new();
+		inter.undeliverable = self.undeliverable;
+		inter.threads = self.threads;
+		inter.limits = self.limits;
+		if let Some(format) = self.trace {
+			inter.set_trace(format, Box::new(io::stderr()));
+		}
+		if self.graph.is_some() {
+			inter.set_graph(MailGraph::new());
+		}
+		if let Some(ref fname) = self.input {
+			let file = match File::open(fname) {
+				Ok(val) => val,
+				Err(err) => {
+					eprintln!("couldn't open {}: {}", fname, err);
+					process::exit(1);
+				}
+			};
+			inter.set_input(Box::new(BufReader::new(file)));
+		}
+		inter
+	}
+
+	/// Applies an option given on the command line, taking its value from
+	/// `args` if it has one. Returns an error if the option is unknown, or its
+	/// value is missing or bad.
+	fn parse_option<I: Iterator<Item=String>>(&mut self, arg: &str, args: &mut I) -> Result<(), String> {
+		match arg {
+			"--bounce" => self.undeliverable = UndeliverableMode::Bounce,
+			"--strict-mail" => self.undeliverable = UndeliverableMode::Error,
+			"--input" => {
+				self.input = Some(try!(args.next().ok_or("Expected a file after --input!".to_string())));
+			},
+			"--trace" => self.trace = Some(TraceFormat::Text),
+			"--trace-json" => self.trace = Some(TraceFormat::Json),
+			"--graph" => {
+				self.graph = Some(try!(args.next().ok_or("Expected a file after --graph!".to_string())));
+			},
+			"--max-frames" | "--max-mails" | "--max-pending" => {
+				let max = Some(try!(parse_number(arg, args.next())));
+				match arg {
+					"--max-frames" => self.limits.frames = max,
+					"--max-mails" => self.limits.mails = max,
+					_ => self.limits.pending = max
+				}
+			},
+			"--timeout" => {
+				match try!(parse_number::<f64>(arg, args.next())) {
+					val if val >= 0.0 => {
+						self.limits.timeout = Some(Duration::from_millis((val * 1000.0) as u64));
+					},
+					_ => return Err("The timeout can not be negative!".to_string())
+				}
+			},
+			"--threads" => {
+				match args.next().and_then(|v|v.parse::<usize>().ok()) {
+					Some(val) if val > 0 => self.threads = val,
+					_ => return Err("Expected a number of threads after --threads!".to_string())
+				}
+			},
+			other => return Err(format!("Unknown option {}!", other))
+		}
+		Ok(())
+	}
+}
+
+/// Reads the given file, panicking if it can not be read.
+fn read_source(fname: &str) -> String {
 	let path = Path::new(&fname);
 	let display = path.display();
 
@@ -31,33 +124,113 @@ fn run(fname: &str) {
 		Err(why) => panic!("couldn't read {}: {}", display, why.description()),
 		Ok(_) => {}
 	};
+	contents
+}
 
-	let instructions = match parser::parse(&contents) {
+/// Checks the given file for mail that can never be delivered, without
+/// running it. Returns false if there were any problems.
+fn check(fname: &str, options: &Options) -> bool {
+	let contents = read_source(fname);
+	let instructions = match parser::parse_file(Path::new(fname), &contents) {
 		Ok(val) => val,
-		Err(err) => {
-			println!("{}", err);
-			if let Some(ref pos) = err.pos {
-				if let Some(ref s) = contents.lines().nth(pos.0 - 1) {
-					// let new_s = s.replace('\t', "    ");
-					let ltrim = s.trim_left();
-					let lspace = &s[0..ltrim.len()];
-					let actual_lspace = lspace.replace('\t', "    ");
-					let dstr = &s[0..pos.1];
-					let dashed_lspace = dstr.chars()
-					                        .map(|c|if c == '\t' {"----"} else {"-"})
-					                        .collect::<String>();
-					println!("{}{}", actual_lspace, ltrim);
-					println!("{}^", dashed_lspace);
-				}
-			}
-			return;
+		Err(errors) => {
+			print!("{}", format_syntax_errors(&contents, &errors));
+			return false;
 		}
 	};
-	let mut inter = interpreter::Interpreter::new();
+	let warnings = analysis::analyze(&instructions, &options.create_interpreter());
+	print!("{}", format_warnings(&contents, &warnings));
+	warnings.len() == 0
+}
+
+/// Runs the given file, returning false if there was an error.
+fn run(fname: &str, options: &Options) -> bool {
+	let contents = read_source(fname);
+	let instructions = match parser::parse_file(Path::new(fname), &contents) {
+		Ok(val) => val,
+		Err(errors) => {
+			print!("{}", format_syntax_errors(&contents, &errors));
+			return false;
+		}
+	};
+	let mut inter = options.create_interpreter();
 
-	inter.execute(&instructions);
+	let result = inter.execute(&instructions);
+	// The graph is written even after an error, to help find where it came from
+	if let (Some(fname), Some(graph)) = (options.graph.as_ref(), inter.get_graph()) {
+		if let Err(err) = File::create(fname).and_then(|mut file|graph.write_dot(&mut file)) {
+			println!("couldn't write {}: {}", fname, err);
+		}
+	}
+	if let Err(err) = result {
+		println!("");
+		println!("{}", err);
+		print_error_pos(&contents, &err.file, &err.pos);
+		return false;
+	}
 
 	println!("");
+	true
+}
+
+/// Formats the line at the given position, with a caret pointing at the column.
+fn format_error_pos(contents: &str, pos: &Option<(usize, usize)>) -> String {
+	if let Some(ref pos) = *pos {
+		if let Some(ref s) = contents.lines().nth(pos.0 - 1) {
+			// let new_s = s.replace('\t', "    ");
+			let ltrim = s.trim_left();
+			let lspace = &s[0..s.len() - ltrim.len()];
+			let actual_lspace = lspace.replace('\t', "    ");
+			let dashed_lspace = s.chars()
+			                     .take(pos.1 - 1)
+			                     .map(|c|if c == '\t' {"----"} else {"-"})
+			                     .collect::<String>();
+			return format!("{}{}\n{}^\n", actual_lspace, ltrim, dashed_lspace);
+		}
+	}
+	"".to_string()
+}
+
+/// Like `format_error_pos`, but with the line taken from the given imported
+/// file, if there is one.
+fn format_error_pos_in(contents: &str, file: &Option<String>, pos: &Option<(usize, usize)>) -> String {
+	match *file {
+		Some(ref file) => {
+			let mut imported = String::new();
+			match File::open(file).and_then(|mut f|f.read_to_string(&mut imported)) {
+				Ok(_) => format_error_pos(&imported, pos),
+				Err(_) => "".to_string()
+			}
+		},
+		None => format_error_pos(contents, pos)
+	}
+}
+
+fn print_error_pos(contents: &str, file: &Option<String>, pos: &Option<(usize, usize)>) {
+	print!("{}", format_error_pos_in(contents, file, pos));
+}
+
+/// Formats every syntax error, each followed by the line it occurred on.
+/// Errors in imported files are shown with lines from those files.
+fn format_syntax_errors(contents: &str, errors: &[SyntaxError]) -> String {
+	let mut ret = String::new();
+	for err in errors {
+		ret.push_str(&format!("{}\n{}", err, format_error_pos_in(contents, &err.file, &err.pos)));
+	}
+	ret
+}
+
+/// Formats every warning, each followed by the line it occurred on, or says
+/// that there are none.
+fn format_warnings(contents: &str, warnings: &[Warning]) -> String {
+	if warnings.len() == 0 {
+		return "No problems found.\n".to_string();
+	}
+	let mut ret = String::new();
+	for warning in warnings {
+		ret.push_str(&format!("{}\n{}", warning, format_error_pos_in(contents, &warning.file, &warning.pos)));
+	}
+	ret
 }
 
 fn help() {
@@ -72,15 +245,68 @@ A programming language based on emails   \    ___________|
                                           \__|      v1.0.0
 See DOC.md for documentation on how to use Emailang.
 Alternatively, see README.md for a quick tutorial.
-emailang <file> - run the given file");
+emailang [options] <file> - run the given file
+emailang [options]        - start interactive mode
+emailang check <file>     - find mail in the given file that can never be
+                            delivered, without running it
+emailang [options] test <dir> - run every .email file in a directory, and
+                            compare its output against its .expected file
+Options:
+    --repl          start interactive mode
+    --help          show this message
+    --bounce        undeliverable mail is bounced back to its sender
+    --strict-mail   undeliverable mail stops the program with an error
+    --input <file>  read input for <io@std.com> from a file
+    --threads <n>   deliver mail to different users on up to n threads
+    --trace         log every frame and delivered mail to standard error
+    --trace-json    like --trace, but log JSON objects, one per line
+    --graph <file>  write who mailed whom to a Graphviz DOT file
+    --max-frames <n>   stop with an error after n frames
+    --max-mails <n>    stop with an error after delivering n mails
+    --max-pending <n>  stop with an error if more than n mails are pending
+    --timeout <secs>   stop with an error after running for this long");
+}
+
+/// Parses the value given after an option, returning an error if it is missing
+/// or not a number.
+fn parse_number<T: FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+	value.and_then(|v|v.parse::<T>().ok()).ok_or(format!("Expected a number after {}!", option))
 }
 
 fn main() {
-	let args = env::args().collect::<Vec<String>>();
-	match args.len() {
-		0 => panic!("This should not be possible!"),
-		1 => help(),
-		2 => run(&args[1]),
+	let mut options = Options::new();
+	let mut positional = Vec::new();
+	let mut args = env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--help" => return help(),
+			"--repl" => {},
+			other if other.starts_with("--") => {
+				if let Err(err) = options.parse_option(other, &mut args) {
+					println!("{}", err);
+					return;
+				}
+			},
+			other => positional.push(other.to_string())
+		}
+	}
+	match positional.len() {
+		0 => repl::repl(&options),
+		1 => {
+			if !run(&positional[0], &options) {
+				process::exit(1);
+			}
+		},
+		2 if positional[0] == "check" => {
+			if !check(&positional[1], &options) {
+				process::exit(1);
+			}
+		},
+		2 if positional[0] == "test" => {
+			if !tester::run_tests(&positional[1], &options) {
+				process::exit(1);
+			}
+		},
 		_ => println!("Invalid number of arguments!")
 	}
 }
@@ -2,52 +2,57 @@ use types::Type;
 use user::UserPath;
 use interpreter::Interpreter;
 use environment::Environment;
+use error::{RuntimeError, RuntimeErrorType};
 use regex;
+use std::sync::Arc;
 
 pub type ModifierFunc = Fn(&Type, &mut Interpreter, &UserPath,
-                           &mut Environment, &[Type]) -> Option<Type>;
+                           &mut Environment, &[Type]) -> Result<Type, RuntimeError> + Send + Sync;
 
 pub fn apply_default_mods(inter: &mut Interpreter) {
-    inter.modifiers.insert("chars".to_string(), Box::new(default_mod_chars));
-    inter.modifiers.insert("merge".to_string(), Box::new(default_mod_merge));
-    inter.modifiers.insert("filter".to_string(), Box::new(default_mod_filter));
+    inter.modifiers.insert("chars".to_string(), Arc::new(default_mod_chars));
+    inter.modifiers.insert("merge".to_string(), Arc::new(default_mod_merge));
+    inter.modifiers.insert("filter".to_string(), Arc::new(default_mod_filter));
 }
 
 fn default_mod_chars(value: &Type, inter: &mut Interpreter, from: &UserPath,
-                     env: &mut Environment, args: &[Type]) -> Option<Type> {
+                     env: &mut Environment, args: &[Type]) -> Result<Type, RuntimeError> {
     if args.len() != 0 {
-        None
+        Err(RuntimeError::new(RuntimeErrorType::BadModifierArguments("chars".to_string())))
     } else {
-        Some(Type::Tuple(value.get_string(inter, from, env).unwrap().chars()
+        Ok(Type::Tuple(try!(value.get_string(inter, from, env)).chars()
             .map(|v|Type::Text(v.to_string())).collect()))
     }
 }
 
 fn default_mod_merge(value: &Type, inter: &mut Interpreter, from: &UserPath,
-                     env: &mut Environment, args: &[Type]) -> Option<Type> {
+                     env: &mut Environment, args: &[Type]) -> Result<Type, RuntimeError> {
     if args.len() != 0 {
-        None
+        Err(RuntimeError::new(RuntimeErrorType::BadModifierArguments("merge".to_string())))
     } else {
-        Some(Type::Text(
-            value.unpack(inter, from, env).iter()
-                 .map(|v|v.get_string(inter, from, env).unwrap())
-                 .collect::<String>()
-        ))
+        let mut ret = String::new();
+        for v in try!(value.unpack(inter, from, env)) {
+            ret.push_str(&try!(v.get_string(inter, from, env)));
+        }
+        Ok(Type::Text(ret))
     }
 }
 
 fn default_mod_filter(value: &Type, inter: &mut Interpreter, from: &UserPath,
-                      env: &mut Environment, args: &[Type]) -> Option<Type> {
+                      env: &mut Environment, args: &[Type]) -> Result<Type, RuntimeError> {
     if args.len() != 1 {
-        None
+        Err(RuntimeError::new(RuntimeErrorType::BadModifierArguments("filter".to_string())))
     } else {
-        let r = regex::Regex::new(&args[0].get_string(inter, from, env).unwrap()).unwrap();
-        Some(Type::Tuple(
-            value.unpack(inter, from, env).iter()
-                 .map(|v|v.get_string(inter, from, env).unwrap())
-                 .filter(|v|r.is_match(&v))
-                 .map(|v|Type::Text(v))
-                 .collect()
-        ))
+        let pattern = try!(args[0].get_string(inter, from, env));
+        let r = try!(regex::Regex::new(&pattern).map_err(
+            |_|RuntimeError::new(RuntimeErrorType::BadRegex(pattern.clone()))));
+        let mut ret = Vec::new();
+        for v in try!(value.unpack(inter, from, env)) {
+            let s = try!(v.get_string(inter, from, env));
+            if r.is_match(&s) {
+                ret.push(Type::Text(s));
+            }
+        }
+        Ok(Type::Tuple(ret))
     }
 }
@@ -1,9 +1,78 @@
-use instruction::Instruction;
-use error::{SyntaxError};
+use instruction::{Instruction, InstructionType};
+use error::{SyntaxError, SyntaxErrorFactory, SyntaxErrorType};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
 pub mod symbolparser;
 pub mod textparser;
 
-pub fn parse(code: &str) -> Result<Vec<Instruction>, SyntaxError> {
-	symbolparser::parse_symbols(&try!(textparser::parse_text(&code)))
+/// Parses a file and every file it imports, keeping track of the files that
+/// are being parsed to find import cycles.
+struct Importer {
+	stack: Vec<PathBuf>
+}
+
+impl Importer {
+	/// Parses code, with imports relative to the given directory.
+	fn parse(&mut self, code: &str, dir: &Path) -> Result<Vec<Instruction>, Vec<SyntaxError>> {
+		let symbols = try!(textparser::parse_text(&code));
+		symbolparser::parse_symbols_with(&symbols, |chunk| {
+			match symbolparser::parse_import(chunk) {
+				Some(Ok(path)) => self.import(&path, dir, &chunk[0].errfactory),
+				Some(Err(err)) => Err(vec![err]),
+				None => symbolparser::parse_statement(chunk)
+			}
+		}).map_err(|mut errors| {
+			// Errors without a position are at the end of the file, and errors
+			// in imported files come after the errors in this one
+			errors.sort_by_key(|err|(err.file.clone(), err.pos.unwrap_or((usize::max_value(), 0))));
+			errors
+		})
+	}
+
+	fn import(&mut self, path: &str, dir: &Path, errfact: &SyntaxErrorFactory)
+	-> Result<Instruction, Vec<SyntaxError>> {
+		let path = dir.join(path);
+		let name = path.display().to_string();
+		let failed = |err: ::std::io::Error| {
+			vec![errfact.gen_error(SyntaxErrorType::ImportFailed(name.clone(), err.to_string()))]
+		};
+		let full_path = try!(path.canonicalize().map_err(&failed));
+		if self.stack.contains(&full_path) {
+			return Err(vec![errfact.gen_error(SyntaxErrorType::ImportCycle(name.clone()))]);
+		}
+		let mut contents = String::new();
+		try!(File::open(&path).and_then(|mut file|file.read_to_string(&mut contents)).map_err(&failed));
+
+		self.stack.push(full_path.clone());
+		let result = self.parse(&contents, path.parent().unwrap_or(Path::new("")));
+		self.stack.pop();
+		let mut block = try!(result.map_err(|errors|errors.into_iter().map(|mut err| {
+			if err.file.is_none() {
+				err.file = Some(name.clone());
+			}
+			err
+		}).collect::<Vec<SyntaxError>>()));
+		for inst in &mut block {
+			inst.set_file(&name);
+		}
+		Ok(Instruction::new(InstructionType::Import(full_path.display().to_string(), name, block),
+			errfact.pos))
+	}
+}
+
+/// Parses code into instructions, or returns every syntax error found, in
+/// order of position. Imported files are relative to the current directory.
+pub fn parse(code: &str) -> Result<Vec<Instruction>, Vec<SyntaxError>> {
+	Importer {stack: Vec::new()}.parse(code, Path::new(""))
+}
+
+/// Parses the code of the file at the given path. Imported files are relative
+/// to the file.
+pub fn parse_file(path: &Path, code: &str) -> Result<Vec<Instruction>, Vec<SyntaxError>> {
+	let mut importer = Importer {
+		stack: path.canonicalize().into_iter().collect()
+	};
+	importer.parse(code, path.parent().unwrap_or(Path::new("")))
 }
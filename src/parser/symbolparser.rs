@@ -1,11 +1,20 @@
 use symbols::{Symbol, SymbolDef};
-use instruction::Instruction;
+use instruction::{Instruction, InstructionType};
 use instruction::CondBlock;
 use user::*;
+use server::{Filter, FilterRule, SenderPattern, RejectAction};
 use types::Type;
+use regex;
 #[allow(unused_imports)]
 use error::{SyntaxErrorFactory, SyntaxErrorType, SyntaxError};
 
+/// Splits symbols at each semicolon, without checking that they end with one.
+fn split_chunks(symbols: &[SymbolDef]) -> Vec<Vec<SymbolDef>> {
+	symbols.split(
+		|v| if let Symbol::Semicolon = v.symbol {true} else {false}
+	).map(|v|v.to_vec()).collect()
+}
+
 pub fn split_semicolon(symbols: &[SymbolDef]) -> Result<Vec<Vec<SymbolDef>>,SyntaxError> {
 	if symbols.len() == 0 {
 		// empty blocks are okay
@@ -17,9 +26,20 @@ pub fn split_semicolon(symbols: &[SymbolDef]) -> Result<Vec<Vec<SymbolDef>>,Synt
 		return Err(symbols.last().unwrap().errfactory.gen_error(SyntaxErrorType::ExpectedSemicolon));
 	}
 	// Split at each semicolon
-	Ok(symbols.split(
-		|v| if let Symbol::Semicolon = v.symbol {true} else {false}
-	).map(|v|v.to_vec()).collect())
+	Ok(split_chunks(symbols))
+}
+
+/// Like split_semicolon, but a missing semicolon is added to errors instead of
+/// stopping, so that parsing can continue.
+fn split_semicolon_recover(symbols: &[SymbolDef], errors: &mut Vec<SyntaxError>)
+-> Vec<Vec<SymbolDef>> {
+	match split_semicolon(symbols) {
+		Ok(val) => val,
+		Err(err) => {
+			errors.push(err);
+			split_chunks(symbols)
+		}
+	}
 }
 
 pub fn is_expression(symbols: &[SymbolDef]) -> bool {
@@ -51,33 +71,137 @@ pub fn split_expression(symbols: &[SymbolDef], errfact: SyntaxErrorFactory)
 	}
 }
 
-fn parse_user_block(block: &[SymbolDef]) -> Result<Vec<(String, Vec<Instruction>)>, SyntaxError> {
+fn parse_user_block(block: &[SymbolDef])
+-> Result<Vec<(Option<String>, Vec<Instruction>, Option<(usize, usize)>)>, Vec<SyntaxError>> {
 	let mut ret = Vec::new();
-	let errfact = SyntaxErrorFactory::from_symbols(block);
-	for chunk in try!(split_semicolon(block)) {
+	let mut errors = Vec::new();
+	let mut has_else = false;
+	for chunk in split_semicolon_recover(block, &mut errors) {
 		if chunk.len() == 0 {
 			continue;
 		}
-		if chunk.len() != 2 {
-			return Err(errfact.gen_error(SyntaxErrorType::BadUserBlock))
+		match parse_handler(&chunk) {
+			Ok(ref val) if val.0.is_none() && has_else => {
+				errors.push(chunk[0].errfactory.gen_error(SyntaxErrorType::DuplicateElse));
+			},
+			Ok(val) => {
+				has_else |= val.0.is_none();
+				ret.push(val);
+			},
+			Err(mut errs) => errors.append(&mut errs)
 		}
-		let name = if let Symbol::Text(ref contents) = chunk[0].symbol {
-			contents
-		} else {
-			return Err(errfact.gen_error(SyntaxErrorType::BadUserBlock))
-		};
-		let block = if let Symbol::CurlyBraced(ref contents) = chunk[1].symbol {
-			contents
-		} else {
-			return Err(errfact.gen_error(SyntaxErrorType::BadUserBlock))
-		};
-		ret.push((name.clone(), try!(parse_symbols(&block.0))));
+	}
+	if errors.len() > 0 {
+		return Err(errors);
 	}
 	Ok(ret)
 }
 
-// TODO: rewrite this monstrosity!
-pub fn parse_ifblock(symbols: &[SymbolDef]) -> Result<Instruction, SyntaxError> {
+/// Parses a handler, either a regex or `else` followed by a block. The else
+/// block is returned without a regex.
+fn parse_handler(chunk: &[SymbolDef])
+-> Result<(Option<String>, Vec<Instruction>, Option<(usize, usize)>), Vec<SyntaxError>> {
+	if chunk.len() != 2 {
+		return Err(vec![chunk[0].errfactory.gen_error(SyntaxErrorType::BadUserBlock)])
+	}
+	let name = match chunk[0].symbol {
+		Symbol::Text(ref contents) => {
+			let regex = match regex::Regex::new(contents) {
+				Ok(val) => val,
+				Err(_) => return Err(vec![chunk[0].errfactory.gen_error(
+					SyntaxErrorType::InvalidRegex(contents.clone()))])
+			};
+			// Named groups are stored as variables, so they can't replace the mail's
+			if let Some(name) = regex.capture_names().filter_map(|v|v)
+				.find(|v|RESERVED_NAMES.contains(v)) {
+				return Err(vec![chunk[0].errfactory.gen_error(
+					SyntaxErrorType::ReservedGroupName(name.to_string()))])
+			}
+			Some(contents.clone())
+		},
+		Symbol::Else => None,
+		_ => return Err(vec![chunk[0].errfactory.gen_error(SyntaxErrorType::BadUserBlock)])
+	};
+	let block = if let Symbol::CurlyBraced(ref contents) = chunk[1].symbol {
+		contents
+	} else {
+		return Err(vec![chunk[1].errfactory.gen_error(SyntaxErrorType::BadUserBlock)])
+	};
+	Ok((name, try!(parse_symbols(&block.0)), chunk[0].errfactory.pos))
+}
+
+fn parse_filter_block(block: &[SymbolDef]) -> Result<Filter, Vec<SyntaxError>> {
+	let mut filter = Filter::new();
+	let mut errors = Vec::new();
+	for chunk in split_semicolon_recover(block, &mut errors) {
+		if chunk.len() == 0 {
+			continue;
+		}
+		match parse_filter_rule(&chunk, &mut filter) {
+			Ok(()) => {},
+			Err(err) => errors.push(err)
+		}
+	}
+	if errors.len() > 0 {
+		return Err(errors);
+	}
+	Ok(filter)
+}
+
+/// Parses a rule such as `block "^spam"`, `allow <*@trusted.com>` or
+/// `rate 10`, adding it to the filter.
+fn parse_filter_rule(chunk: &[SymbolDef], filter: &mut Filter) -> Result<(), SyntaxError> {
+	let bad_rule = || chunk[0].errfactory.gen_error(SyntaxErrorType::BadFilterRule);
+	let keyword = match chunk[0].symbol {
+		Symbol::Identifier(ref val) => val.as_str(),
+		_ => return Err(bad_rule())
+	};
+	match (keyword, &chunk[1..]) {
+		("bounce", []) => filter.action = RejectAction::Bounce,
+		("log", []) => filter.action = RejectAction::Log,
+		("rate", [ref arg]) => {
+			let max = match arg.symbol {
+				Symbol::Identifier(ref val) | Symbol::Text(ref val) => val.parse::<usize>().ok(),
+				_ => None
+			};
+			filter.rules.push(FilterRule::Rate(try!(max.ok_or(
+				arg.errfactory.gen_error(SyntaxErrorType::BadFilterRule)))));
+		},
+		("block", [ref arg]) | ("allow", [ref arg]) => {
+			let block = keyword == "block";
+			let rule = match arg.symbol {
+				Symbol::Text(ref val) => {
+					let re = try!(regex::Regex::new(val).map_err(
+						|_|arg.errfactory.gen_error(SyntaxErrorType::InvalidRegex(val.clone()))));
+					if block {FilterRule::BlockSubject(re)} else {FilterRule::AllowSubject(re)}
+				},
+				Symbol::UserPath(ref name, ref server) => {
+					let pat = SenderPattern(try!(parse_pattern_part(&name.0, arg)),
+						try!(parse_pattern_part(&server.0, arg)));
+					if block {FilterRule::BlockSender(pat)} else {FilterRule::AllowSender(pat)}
+				},
+				_ => return Err(arg.errfactory.gen_error(SyntaxErrorType::BadFilterRule))
+			};
+			filter.rules.push(rule);
+		},
+		_ => return Err(bad_rule())
+	}
+	Ok(())
+}
+
+/// Parses the username or server of a sender pattern, where `*` matches anything.
+fn parse_pattern_part(symbols: &[SymbolDef], path: &SymbolDef) -> Result<Option<String>, SyntaxError> {
+	match symbols {
+		[ref s] => match s.symbol {
+			Symbol::Receive => Ok(None),
+			Symbol::Identifier(ref val) | Symbol::Text(ref val) => Ok(Some(val.clone())),
+			_ => Err(s.errfactory.gen_error(SyntaxErrorType::MalformedUserpath))
+		},
+		_ => Err(path.errfactory.gen_error(SyntaxErrorType::MalformedUserpath))
+	}
+}
+
+pub fn parse_ifblock(symbols: &[SymbolDef]) -> Result<Instruction, Vec<SyntaxError>> {
 	let mut blocks = Vec::new();
 	let mut from_pos = 0;
 	for i in 1..symbols.len() {
@@ -90,54 +214,78 @@ pub fn parse_ifblock(symbols: &[SymbolDef]) -> Result<Instruction, SyntaxError>
 	}
 	blocks.push(&symbols[from_pos..]);
 
-	let mut ifblk = None;
+	let mut ifblk: Option<CondBlock> = None;
+	let mut errors = Vec::new();
 
 	for block in &blocks {
-		let blklen = block.len();
-		match block[0].symbol {
-			Symbol::If | Symbol::ElseIf => {
-				let exp = &block[1..blklen-1];
-				let curlybracket = match block.last().unwrap().symbol {
-					Symbol::CurlyBraced(ref b) => &b.0,
-					_ => panic!()
-				};
-
-				if let Symbol::If = block[0].symbol {
-					assert!(ifblk.is_none());
-					ifblk = Some(CondBlock {
-						cond: Some(try!(parse_type(&exp))),
-						block: try!(parse_symbols(&curlybracket)),
-						elseblock: None
-					});
-				} else {
-					assert!(ifblk.is_some());
-					ifblk.as_mut().unwrap().append_block(CondBlock {
-						cond: Some(try!(parse_type(&exp))),
-						block: try!(parse_symbols(&curlybracket)),
-						elseblock: None
-					});
+		match parse_condblock(block, &ifblk) {
+			Ok((condblock, result)) => {
+				// Keep going after errors in the block, to find errors in other blocks
+				if let Err(mut errs) = result {
+					errors.append(&mut errs);
+				}
+				match ifblk {
+					Some(ref mut val) => val.append_block(condblock),
+					None => ifblk = Some(condblock)
 				}
 			},
-			Symbol::Else => {
-				let exp = &block[1..];
-				assert!(exp.len() == 1);
-				let curlybracket = match block.last().unwrap().symbol {
-					Symbol::CurlyBraced(ref b) => &b.0,
-					_ => panic!()
-				};
-				assert!(ifblk.is_some());
-				ifblk.as_mut().unwrap().append_block(CondBlock {
-					cond: None,
-					block: try!(parse_symbols(&curlybracket)),
-					elseblock: None
-				});
-			},
-			_ => panic!()
+			Err(err) => {
+				errors.push(err);
+				return Err(errors);
+			}
 		}
 	}
+	if errors.len() > 0 {
+		return Err(errors);
+	}
+
+	ifblk.map(|val|Instruction::new(InstructionType::IfBlock(val), symbols[0].errfactory.pos))
+	.ok_or(vec![symbols[0].errfactory.gen_error(SyntaxErrorType::MalformedIfStatement)])
+}
 
-	ifblk.map(|val|Instruction::IfBlock(val))
-	.ok_or(symbols[0].errfactory.gen_error(SyntaxErrorType::MalformedIfStatement))
+/// Parses a single if, elif or else block, given the blocks before it. Errors
+/// in the block's body are returned alongside it, so that parsing can continue.
+fn parse_condblock(block: &[SymbolDef], prev: &Option<CondBlock>)
+-> Result<(CondBlock, Result<(), Vec<SyntaxError>>), SyntaxError> {
+	let errfact = &block[0].errfactory;
+	// Every block is made of a keyword, an optional condition and a curly braced block
+	let last = block.last().unwrap();
+	let curlybracket = match last.symbol {
+		Symbol::CurlyBraced(ref b) if block.len() >= 2 => &b.0,
+		_ => return Err(last.errfactory.gen_error(SyntaxErrorType::ExpectedBlock))
+	};
+	let exp = &block[1..block.len()-1];
+	if let Some(ref prev) = *prev {
+		if prev.has_else() {
+			return Err(errfact.gen_error(SyntaxErrorType::ElseNotLast));
+		}
+	}
+	let cond = match (&block[0].symbol, prev.is_some()) {
+		(&Symbol::If, true) => return Err(errfact.gen_error(SyntaxErrorType::UnexpectedIf)),
+		(&Symbol::If, false) | (&Symbol::ElseIf, true) => {
+			if exp.len() == 0 {
+				return Err(errfact.gen_error(SyntaxErrorType::MissingCondition));
+			}
+			Some(try!(parse_type(&exp)))
+		},
+		(&Symbol::Else, true) => {
+			if exp.len() != 0 {
+				return Err(exp[0].errfactory.gen_error(SyntaxErrorType::ElseWithCondition));
+			}
+			None
+		},
+		_ => return Err(errfact.gen_error(SyntaxErrorType::MalformedIfStatement))
+	};
+	let (body, result) = match parse_symbols(&curlybracket) {
+		Ok(val) => (val, Ok(())),
+		Err(errs) => (Vec::new(), Err(errs))
+	};
+	Ok((CondBlock {
+		pos: errfact.pos,
+		cond: cond,
+		block: body,
+		elseblock: None
+	}, result))
 }
 
 pub fn parse_type(symbols: &[SymbolDef]) -> Result<Type, SyntaxError> {
@@ -161,15 +309,15 @@ pub fn parse_expression(symbols: &[SymbolDef], errfact: SyntaxErrorFactory) -> R
 	if postval.is_null() == mid.get_operator().postval() {
 		return Err(mid.errfactory.gen_error(SyntaxErrorType::BadExpression));
 	}
-	match mid.symbol {
-		Symbol::Arrow => Ok(Instruction::MailTo(preval, postval)),
-		Symbol::Addition => Ok(Instruction::Concatenate(preval, postval)),
-		Symbol::Assign => Ok(Instruction::Assign(preval, postval)),
-		Symbol::Modifier => Ok(Instruction::Modify(preval, postval)),
-		Symbol::Receive => Ok(Instruction::GetEnv(postval)),
-		Symbol::Index(ref pos) => Ok(Instruction::Index(preval, try!(parse_type(&pos.0)))),
+	let insttype = match mid.symbol {
+		Symbol::Arrow => InstructionType::MailTo(preval, postval),
+		Symbol::Addition => InstructionType::Concatenate(preval, postval),
+		Symbol::Assign => InstructionType::Assign(preval, postval),
+		Symbol::Modifier => InstructionType::Modify(preval, postval),
+		Symbol::Receive => InstructionType::GetEnv(postval),
+		Symbol::Index(ref pos) => InstructionType::Index(preval, try!(parse_type(&pos.0))),
 		Symbol::Slice(ref pos1, ref pos2) => {
-			Ok(Instruction::Slice(preval,
+			InstructionType::Slice(preval,
 				match *pos1 {
 					Some(ref val) => Some(try!(parse_type(&val.0))),
 					None => None
@@ -178,55 +326,123 @@ pub fn parse_expression(symbols: &[SymbolDef], errfact: SyntaxErrorFactory) -> R
 					Some(ref val) => Some(try!(parse_type(&val.0))),
 					None => None
 				}
-			))
+			)
 		},
-		_ => Err(symbols[0].errfactory.gen_error(SyntaxErrorType::BadExpression))
-	}
+		_ => return Err(symbols[0].errfactory.gen_error(SyntaxErrorType::BadExpression))
+	};
+	Ok(Instruction::new(insttype, mid.errfactory.pos))
+}
+
+/// Parses a block of statements. Parsing continues after a bad statement, so
+/// that every error in the block can be reported at once.
+pub fn parse_symbols(symbols: &[SymbolDef]) -> Result<Vec<Instruction>, Vec<SyntaxError>> {
+	parse_symbols_with(symbols, parse_statement)
 }
 
-pub fn parse_symbols(symbols: &[SymbolDef]) -> Result<Vec<Instruction>, SyntaxError> {
+/// Like parse_symbols, but every statement is parsed with the given function.
+pub fn parse_symbols_with<F>(symbols: &[SymbolDef], mut parse: F)
+-> Result<Vec<Instruction>, Vec<SyntaxError>>
+where F: FnMut(&[SymbolDef]) -> Result<Instruction, Vec<SyntaxError>> {
 	let mut ret = Vec::new();
-	for chunk in try!(split_semicolon(symbols)) {
+	let mut errors = Vec::new();
+	for chunk in split_semicolon_recover(symbols, &mut errors) {
 		if chunk.len() == 0 {
 			continue;
 		}
+		match parse(&chunk) {
+			Ok(inst) => ret.push(inst),
+			Err(mut errs) => errors.append(&mut errs)
+		}
+	}
+	if errors.len() > 0 {
+		return Err(errors);
+	}
+	Ok(ret)
+}
 
-		let inst = if let Symbol::Define = chunk[0].symbol {
-			assert!(chunk.len() >= 2);
-			let ref symbol = chunk[1].symbol;
-			let chunk = &chunk[1..];
-			match *symbol {
-				Symbol::UserPath(ref name, ref server) => {
-					let block = match chunk.len() {
-						1 => Vec::new(),
-						2 => {
-							if let Symbol::CurlyBraced(ref block) = chunk[1].symbol {
-								try!(parse_user_block(&block.0))
-							} else {
-								return Err(chunk[1].errfactory.gen_error(
-									SyntaxErrorType::BadUserBlock))
-							}
+/// If the statement is an import, e.g. `!import "foo.email"`, returns the
+/// path of the file to import.
+pub fn parse_import(chunk: &[SymbolDef]) -> Option<Result<String, SyntaxError>> {
+	match (chunk.get(0).map(|v|&v.symbol), chunk.get(1).map(|v|&v.symbol)) {
+		(Some(&Symbol::Define), Some(&Symbol::Identifier(ref name))) if name == "import" => {},
+		_ => return None
+	}
+	Some(match (chunk.len(), &chunk[chunk.len()-1].symbol) {
+		(3, &Symbol::Text(ref path)) => Ok(path.clone()),
+		_ => Err(chunk[1].errfactory.gen_error(SyntaxErrorType::BadImport))
+	})
+}
+
+pub fn parse_statement(chunk: &[SymbolDef]) -> Result<Instruction, Vec<SyntaxError>> {
+	let pos = chunk[0].errfactory.pos;
+	// Imports are handled before this, by the parser of the whole file
+	if parse_import(chunk).is_some() {
+		return Err(vec![chunk[0].errfactory.gen_error(SyntaxErrorType::ImportNotAtTopLevel)]);
+	}
+	Ok(if let Symbol::Define = chunk[0].symbol {
+		if chunk.len() < 2 {
+			return Err(vec![chunk[0].errfactory.gen_error(SyntaxErrorType::BadDefinition(None))]);
+		}
+		let ref symbol = chunk[1].symbol;
+		let chunk = &chunk[1..];
+		match *symbol {
+			Symbol::UserPath(ref name, ref server) => {
+				// The user may inherit the handlers of another, e.g. `!<a@b> : <c@d>`
+				let (parent, chunk) = match chunk.get(1).map(|v|&v.symbol) {
+					Some(&Symbol::Colon) => match chunk.get(2) {
+						Some(parent @ &SymbolDef{symbol: Symbol::UserPath(_, _), ..}) => {
+							(Some(try!(parent.get_type())), &chunk[2..])
 						},
-						_ => return Err(chunk[1].errfactory.gen_error(
-							SyntaxErrorType::BadUserBlock))
-					};
-					let user = UserDef::create_def_internal(block);
-					Instruction::CreateUser(
-						try!(parse_type(&name.0)),
-						try!(parse_type(&server.0)),
-						user)
-				},
-				_ => {
-					Instruction::CreateServer(try!(parse_type(&chunk)))
-				}
+						_ => return Err(vec![chunk[1].errfactory.gen_error(
+							SyntaxErrorType::ExpectedParent)])
+					},
+					_ => (None, chunk)
+				};
+				// `all` runs every matching handler instead of only the first
+				let (all_matches, rest) = match chunk.get(1).map(|v|&v.symbol) {
+					Some(&Symbol::Identifier(ref val)) if val == "all" => (true, &chunk[2..]),
+					_ => (false, &chunk[1..])
+				};
+				let block = match rest.len() {
+					0 => Vec::new(),
+					1 => {
+						if let Symbol::CurlyBraced(ref block) = rest[0].symbol {
+							try!(parse_user_block(&block.0))
+						} else {
+							return Err(vec![rest[0].errfactory.gen_error(
+								SyntaxErrorType::BadUserBlock)])
+						}
+					},
+					_ => return Err(vec![rest[0].errfactory.gen_error(
+						SyntaxErrorType::BadUserBlock)])
+				};
+				let user = try!(UserDef::create_def_internal(block, all_matches).map_err(
+					|err|vec![chunk[0].errfactory.gen_error(SyntaxErrorType::InvalidRegex(err.to_string()))]));
+				Instruction::new(InstructionType::CreateUser(
+					try!(parse_type(&name.0)),
+					try!(parse_type(&server.0)),
+					user, parent), pos)
+			},
+			_ => {
+				// A server may be followed by its spam filter rules
+				let (name, filter) = match chunk.last().unwrap().symbol {
+					Symbol::CurlyBraced(ref block) if chunk.len() > 1 => {
+						(&chunk[..chunk.len()-1], try!(parse_filter_block(&block.0)))
+					},
+					_ => (chunk, Filter::new())
+				};
+				Instruction::new(InstructionType::CreateServer(try!(parse_type(name)), filter), pos)
 			}
-		} else if let Symbol::If = chunk[0].symbol {
-			try!(parse_ifblock(&chunk))
-		} else {
-			// expressions
-			try!(parse_expression(&chunk, SyntaxErrorFactory::from_symbols(&chunk)))
-		};
-		ret.push(inst);
-	}
-	Ok(ret)
+		}
+	} else if let Symbol::If = chunk[0].symbol {
+		try!(parse_ifblock(&chunk))
+	} else if let (1, &Symbol::Identifier(ref val)) = (chunk.len(), &chunk[0].symbol) {
+		if val != "continue" {
+			return Err(vec![chunk[0].errfactory.gen_error(SyntaxErrorType::BadExpression)]);
+		}
+		Instruction::new(InstructionType::Continue, pos)
+	} else {
+		// expressions
+		try!(parse_expression(&chunk, SyntaxErrorFactory::from_symbols(&chunk)))
+	})
 }
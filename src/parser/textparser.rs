@@ -3,6 +3,7 @@ use symbols::{Symbol, SymbolDef};
 use std::slice::Iter;
 use error::{SyntaxErrorFactory, SyntaxError, SyntaxErrorType};
 use std::fmt;
+use std::char;
 
 #[derive(Clone, Copy)]
 pub struct CodeChar {
@@ -54,15 +55,12 @@ pub fn take_until_unescaped(chars: &mut Iter<CodeChar>, m: char) -> Vec<CodeChar
 		match n {
 			None => break,
 			Some(other) => {
-				if other.val == '\\' && is_esc {
-					is_esc = true;
-				} else {
-					if other.val == m {
-						if !is_esc {
-							break
-						}
-					}
+				if is_esc {
 					is_esc = false;
+				} else if other.val == '\\' {
+					is_esc = true;
+				} else if other.val == m {
+					break
 				}
 				ret.push(*other);
 			}
@@ -71,6 +69,62 @@ pub fn take_until_unescaped(chars: &mut Iter<CodeChar>, m: char) -> Vec<CodeChar
 	ret
 }
 
+/// Replaces escape sequences such as `\n` with the characters they represent.
+pub fn unescape(chars: &[CodeChar]) -> Result<String, SyntaxError> {
+	let mut ret = String::new();
+	let mut iter = chars.iter();
+	loop {
+		let c = match iter.next() {
+			Some(val) => val,
+			None => break
+		};
+		if c.val != '\\' {
+			ret.push(c.val);
+			continue;
+		}
+		let bad_escape = |s: String| SyntaxError::new(c.line, c.column,
+			SyntaxErrorType::InvalidEscape(s));
+		let escaped = match iter.next() {
+			Some(val) => val.val,
+			None => return Err(bad_escape("\\".to_string()))
+		};
+		ret.push(match escaped {
+			'"' => '"',
+			'\\' => '\\',
+			'n' => '\n',
+			't' => '\t',
+			'r' => '\r',
+			'u' => {
+				let mut seq = "\\u".to_string();
+				let mut code = String::new();
+				let mut closed = false;
+				if let Some(val) = iter.next() {
+					seq.push(val.val);
+					if val.val == '{' {
+						while let Some(val) = iter.next() {
+							seq.push(val.val);
+							if val.val == '}' {
+								closed = true;
+								break;
+							}
+							code.push(val.val);
+						}
+					}
+				}
+				if !closed {
+					return Err(bad_escape(seq));
+				}
+				match u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+					Some(val) => val,
+					None => return Err(bad_escape(seq))
+				}
+			},
+			other => return Err(bad_escape(format!("\\{}", other)))
+		});
+	}
+	Ok(ret)
+}
+
 pub fn take_until_matched(chars: &mut Iter<CodeChar>, begin: char, end: char, target_level: i32) -> Vec<CodeChar> {
 	let mut ret = Vec::new();
 	let mut level: i32 = 1;
@@ -97,18 +151,20 @@ pub fn take_until_matched(chars: &mut Iter<CodeChar>, begin: char, end: char, ta
 
 pub fn parse_text(code: &str) -> Result<Vec<SymbolDef>, SyntaxError> {
 	let mut line = 1;
-	let mut column = 0;
+	let mut column = 1;
 	parse_code(&code.chars().map(|v| {
-		if v == '\n' {
-			line += 1;
-			column = 0;
-		}
-		column += 1;
-		CodeChar {
+		let c = CodeChar {
 			val: v,
 			line: line,
 			column: column
+		};
+		if v == '\n' {
+			line += 1;
+			column = 1;
+		} else {
+			column += 1;
 		}
+		c
 	}).collect::<Vec<CodeChar>>())
 }
 
@@ -131,6 +187,8 @@ pub fn parse_code(code: &[CodeChar]) -> Result<Vec<SymbolDef>, SyntaxError> {
 	let mut ret = Vec::new();
 	let mut chars = code.iter();
 	let mut text = String::new();
+	// Where the identifier currently in text begins
+	let mut text_start = None;
 	let mut lastchar = None;
 	loop {
 		let c = match chars.next() {
@@ -141,6 +199,7 @@ pub fn parse_code(code: &[CodeChar]) -> Result<Vec<SymbolDef>, SyntaxError> {
 		let s = match c.val {
 			'!' => Symbol::Define,
 			',' => Symbol::Comma,
+			':' => Symbol::Colon,
 			';' => Symbol::Semicolon,
 			'>' => Symbol::Arrow,
 			'+' => Symbol::Addition,
@@ -168,7 +227,13 @@ pub fn parse_code(code: &[CodeChar]) -> Result<Vec<SymbolDef>, SyntaxError> {
 				Symbol::Parenthesis(symbols::Block(try!(parse_code(&block))))
 			},
 			'"' => {
-				Symbol::Text(codechars_to_string(&take_until_unescaped(&mut chars, '"')))
+				if text == "r" {
+					// Raw text, where backslashes have no special meaning
+					text.clear();
+					Symbol::Text(codechars_to_string(&take_until(&mut chars, '"')))
+				} else {
+					Symbol::Text(try!(unescape(&take_until_unescaped(&mut chars, '"'))))
+				}
 			},
 			'[' => {
 				let indexcontents = take_until(&mut chars, ']');
@@ -198,23 +263,27 @@ pub fn parse_code(code: &[CodeChar]) -> Result<Vec<SymbolDef>, SyntaxError> {
 			},
 			other => {
 				if other.is_alphanumeric() || ['.', '@', '_', '-'].contains(&other) {
+					if text.len() == 0 {
+						text_start = Some(*c);
+					}
 					text.push(other);
 				} else if other.is_whitespace() {
-					take_identifier(&mut ret, &mut text, &c);
+					take_identifier(&mut ret, &mut text, &text_start.unwrap_or(*c));
 				} else {
-					panic!("{} is not a valid character!", other);
+					return Err(SyntaxError::new(c.line, c.column,
+						SyntaxErrorType::UnexpectedSymbol(other)));
 				}
 				continue;
 			}
 		};
-		take_identifier(&mut ret, &mut text, &c);
+		take_identifier(&mut ret, &mut text, &text_start.unwrap_or(*c));
 		ret.push(SymbolDef{
 			symbol: s,
 			errfactory: SyntaxErrorFactory::new(c.line, c.column)
 		});
 	}
 	if let Some(ref lastc) = lastchar {
-		take_identifier(&mut ret, &mut text, &lastc);
+		take_identifier(&mut ret, &mut text, &text_start.unwrap_or(*lastc));
 	}
 	Ok(ret)
 }
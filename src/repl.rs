@@ -0,0 +1,170 @@
+use emailang::interpreter::Interpreter;
+use emailang::environment::Environment;
+use emailang::parser;
+use {print_error_pos, format_syntax_errors};
+use Options;
+use std::io::{self, BufRead, Write};
+
+/// Returns true if the code contains at least one full statement, and no
+/// unclosed blocks or strings.
+fn is_complete(code: &str) -> bool {
+	let mut level: i32 = 0;
+	let mut chars = code.chars();
+	let mut last = None;
+	// The identifier directly before the current character, to detect raw text
+	let mut word = String::new();
+	loop {
+		let c = match chars.next() {
+			Some(val) => val,
+			None => break
+		};
+		match c {
+			'{' | '(' => level += 1,
+			'}' | ')' => level -= 1,
+			'"' => {
+				let is_raw = word == "r";
+				let mut is_esc = false;
+				loop {
+					match chars.next() {
+						None => return false,
+						Some('\\') if !is_esc && !is_raw => is_esc = true,
+						Some('"') if !is_esc => break,
+						Some(_) => is_esc = false
+					}
+				}
+			},
+			'#' => {
+				while let Some(val) = chars.next() {
+					if val == '\n' {
+						break;
+					}
+				}
+				continue;
+			},
+			_ => {}
+		}
+		if c.is_alphanumeric() || ['.', '@', '_', '-'].contains(&c) {
+			word.push(c);
+		} else {
+			word.clear();
+		}
+		if !c.is_whitespace() {
+			last = Some(c);
+		}
+	}
+	level <= 0 && last == Some(';')
+}
+
+fn help() {
+	println!(
+r"Enter statements to run them as the anonymous user.
+:servers              - list all servers
+:users <server>       - list all users in a server
+:env [<user@server>]  - show a user's environment, or the anonymous user's
+:pending              - list mail that has not been delivered yet
+:help                 - show this message
+:quit                 - exit");
+}
+
+fn print_env(env: &Environment) {
+	let mut keys = env.data.keys().collect::<Vec<&String>>();
+	keys.sort();
+	for key in keys {
+		println!("{} = {}", key, env.data[key]);
+	}
+}
+
+/// Runs a meta-command. Returns false if the REPL should exit.
+fn meta_command(inter: &Interpreter, env: &Environment, line: &str) -> bool {
+	let mut words = line.split_whitespace();
+	let command = words.next().unwrap_or("");
+	let arg = words.next();
+	match (command, arg) {
+		(":quit", _) | (":q", _) => return false,
+		(":help", _) => help(),
+		(":servers", _) => {
+			for name in inter.get_server_names() {
+				println!("{}", name);
+			}
+		},
+		(":users", Some(server)) => {
+			match inter.get_server(server) {
+				Some(serv) => {
+					for name in serv.get_user_names() {
+						println!("<{}@{}>", name, server);
+					}
+				},
+				None => println!("Server '{}' does not exist!", server)
+			}
+		},
+		(":env", None) => print_env(env),
+		(":env", Some(path)) => {
+			let path = path.trim_start_matches('<').trim_end_matches('>');
+			let mut parts = path.splitn(2, '@');
+			let (name, server) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+			match inter.get_server(server).and_then(|serv|serv.get_user(name)) {
+				Some(user) => print_env(&user.env),
+				None => println!("User <{}@{}> does not exist!", name, server)
+			}
+		},
+		(":pending", _) => {
+			for mail in inter.get_pending() {
+				println!("{}", mail);
+			}
+		},
+		(other, _) => println!("Bad command {}! Type :help for a list of commands.", other)
+	}
+	true
+}
+
+pub fn repl(options: &Options) {
+	let mut inter = options.create_interpreter();
+	let mut env = Environment::new();
+	let stdin = io::stdin();
+	let mut code = String::new();
+	println!("Emailang v1.0.0 interactive mode. Type :help for a list of commands.");
+	loop {
+		print!("{}", if code.len() == 0 {"> "} else {". "});
+		io::stdout().flush().unwrap();
+		let mut line = String::new();
+		match stdin.lock().read_line(&mut line) {
+			Ok(0) | Err(_) => break,
+			Ok(_) => {}
+		}
+		if code.len() == 0 && line.trim().starts_with(':') {
+			if !meta_command(&inter, &env, line.trim()) {
+				break;
+			}
+			continue;
+		}
+		code.push_str(&line);
+		if code.trim().len() == 0 {
+			code.clear();
+			continue;
+		}
+		if !is_complete(&code) {
+			continue;
+		}
+		let statement = code.split_off(0);
+		match parser::parse(&statement) {
+			Ok(instructions) => {
+				if let Err(err) = inter.execute_env(&instructions, &mut env) {
+					println!("{}", err);
+					// Errors inside of handlers come from earlier statements
+					if err.handler.is_none() {
+						print_error_pos(&statement, &err.file, &err.pos);
+					}
+				}
+			},
+			Err(errors) => print!("{}", format_syntax_errors(&statement, &errors))
+		}
+	}
+	println!("");
+	// A statement left unfinished when the input ends is never run
+	if code.trim().len() > 0 {
+		match parser::parse(&code) {
+			Ok(_) => println!("The last statement is incomplete, and was not run!"),
+			Err(errors) => print!("{}", format_syntax_errors(&code, &errors))
+		}
+	}
+}
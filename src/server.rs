@@ -1,11 +1,100 @@
-use user::User;
+use user::{User, UserPath};
+use mail::Mail;
 use std::collections::HashMap;
+use regex;
+
+/// Matches senders by username and server, where None matches anything.
+#[derive(Clone, Debug)]
+pub struct SenderPattern(pub Option<String>, pub Option<String>);
+
+impl SenderPattern {
+	pub fn matches(&self, path: &UserPath) -> bool {
+		self.0.as_ref().map(|v|*v == path.0).unwrap_or(true) &&
+		self.1.as_ref().map(|v|*v == path.1).unwrap_or(true)
+	}
+}
+
+#[derive(Clone, Debug)]
+pub enum FilterRule {
+	/// Rejects mail whose subject matches the regex.
+	BlockSubject(regex::Regex),
+	/// Rejects mail from matching senders.
+	BlockSender(SenderPattern),
+	/// Accepts mail whose subject matches the regex, skipping the rules after it.
+	AllowSubject(regex::Regex),
+	/// Accepts mail from matching senders, skipping the rules after it.
+	AllowSender(SenderPattern),
+	/// Rejects mail once a sender has sent this many to the server in a frame.
+	Rate(usize)
+}
+
+/// What a server does with mail its filter rejects.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RejectAction {
+	Drop,
+	Bounce,
+	Log
+}
+
+/// A server's spam filter. Rules are checked in order, and the first one that
+/// accepts or rejects the mail decides what happens to it.
+#[derive(Clone, Debug)]
+pub struct Filter {
+	pub rules: Vec<FilterRule>,
+	pub action: RejectAction,
+	counts: HashMap<(String, String), usize>
+}
+
+impl Filter {
+	pub fn new() -> Filter {
+		Filter {
+			rules: Vec::new(),
+			action: RejectAction::Drop,
+			counts: HashMap::new()
+		}
+	}
+
+	/// Returns false if the mail should be rejected.
+	pub fn check(&mut self, mail: &Mail) -> bool {
+		for rule in &self.rules {
+			match *rule {
+				FilterRule::BlockSubject(ref re) if re.is_match(&mail.subject) => return false,
+				FilterRule::BlockSender(ref pat) if pat.matches(&mail.from) => return false,
+				FilterRule::AllowSubject(ref re) if re.is_match(&mail.subject) => return true,
+				FilterRule::AllowSender(ref pat) if pat.matches(&mail.from) => return true,
+				FilterRule::Rate(max) => {
+					let count = self.counts.entry((mail.from.0.clone(), mail.from.1.clone()))
+						.or_insert(0);
+					*count += 1;
+					if *count > max {
+						return false;
+					}
+				},
+				_ => {}
+			}
+		}
+		true
+	}
+
+	/// Starts a new frame, resetting how much mail each sender has sent.
+	pub fn new_frame(&mut self) {
+		self.counts.clear();
+	}
+}
 
 pub struct Server {
-	pub users: HashMap<String, User>
+	pub users: HashMap<String, User>,
+	pub filter: Filter
 }
 
 impl Server {
+	pub fn new(filter: Filter) -> Server {
+		Server {
+			users: HashMap::new(),
+			filter: filter
+		}
+	}
+
 	pub fn get_user(&self, name: &str) -> Option<&User> {
 		self.users.get(name)
 	}
@@ -14,6 +103,13 @@ impl Server {
 		self.users.get_mut(name)
 	}
 
+	/// Returns the names of every user, in alphabetical order.
+	pub fn get_user_names(&self) -> Vec<&str> {
+		let mut names = self.users.keys().map(|v|v.as_str()).collect::<Vec<&str>>();
+		names.sort();
+		names
+	}
+
 	pub fn add_user(&mut self, name: String, user: User) {
 		self.users.insert(name, user);
 	}
@@ -46,6 +46,7 @@ impl Block {
  * (a,b,c)  - parenthesis
  * >        - arrow
  * ,        - comma
+ * :        - colon
  * ;        - semicolon
 **/
 
@@ -60,6 +61,7 @@ pub enum Symbol {
 	Text(String),
 	// Syntax
 	Comma,
+	Colon,
 	Semicolon,
 	Index(Block),
 	Slice(Option<Block>, Option<Block>),
@@ -157,7 +159,9 @@ impl SymbolDef {
 						Ok(Type::Expression(Box::new(try!(symbolparser::parse_expression(&val.0,
 							SyntaxErrorFactory::from_symbols(&val.0))))))
 					} else {
-						assert!(val.0.len() == 1);
+						if val.0.len() > 1 {
+							return Err(val.0[1].errfactory.gen_error(SyntaxErrorType::ExpectedComma));
+						}
 						Ok(try!(try!(val.0.get(0)
 							.ok_or(self.errfactory.gen_error(SyntaxErrorType::BadExpression)))
 							.get_type()))
@@ -0,0 +1,184 @@
+use emailang::OutputBuffer;
+use emailang::{parser, analysis};
+use {format_error_pos_in, format_syntax_errors, format_warnings};
+use Options;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+
+fn read_file(path: &Path) -> io::Result<String> {
+	let mut contents = String::new();
+	try!(try!(File::open(path)).read_to_string(&mut contents));
+	Ok(contents)
+}
+
+/// Line endings and trailing newlines are not significant when comparing output.
+fn normalize(output: &str) -> String {
+	output.replace("\r\n", "\n").trim_end_matches('\n').to_string()
+}
+
+/// Runs a program, returning everything it printed, including errors.
+fn run_program(path: &Path, options: &Options) -> io::Result<String> {
+	let contents = try!(read_file(path));
+	let input = match read_file(&path.with_extension("input")) {
+		Ok(val) => val,
+		Err(_) => String::new()
+	};
+
+	let instructions = match parser::parse_file(path, &contents) {
+		Ok(val) => val,
+		Err(errors) => return Ok(format_syntax_errors(&contents, &errors))
+	};
+	let output = OutputBuffer::new();
+	let mut inter = options.create_interpreter();
+	inter.set_output(Box::new(output.clone()));
+	inter.set_input(Box::new(Cursor::new(input.into_bytes())));
+	// The trace and graph are compared with the output, instead of being
+	// written elsewhere
+	if let Some(format) = options.trace {
+		inter.set_trace(format, Box::new(output.clone()));
+	}
+	let result = inter.execute(&instructions);
+	if let Some(graph) = inter.get_graph() {
+		try!(graph.write_dot(&mut output.clone()));
+	}
+	if let Err(err) = result {
+		return Ok(format!("{}\n{}\n{}", output.get_string(), err,
+			format_error_pos_in(&contents, &err.file, &err.pos)));
+	}
+	Ok(output.get_string())
+}
+
+/// Returns the options to run a test with, which are the given options
+/// followed by those in the .args file next to it, if there is one.
+fn test_options(path: &Path, options: &Options) -> Result<Options, String> {
+	let mut options = options.clone();
+	if let Ok(args) = read_file(&path.with_extension("args")) {
+		let mut args = args.split_whitespace().map(|v|v.to_string());
+		while let Some(arg) = args.next() {
+			try!(options.parse_option(&arg, &mut args));
+		}
+	}
+	Ok(options)
+}
+
+/// Checks a program without running it, returning the warnings it printed.
+fn check_program(path: &Path, options: &Options) -> io::Result<String> {
+	let contents = try!(read_file(path));
+	let instructions = match parser::parse_file(path, &contents) {
+		Ok(val) => val,
+		Err(errors) => return Ok(format_syntax_errors(&contents, &errors))
+	};
+	let warnings = analysis::analyze(&instructions, &options.create_interpreter());
+	Ok(format_warnings(&contents, &warnings))
+}
+
+/// Prints the differences between the expected and actual output, line by line.
+fn print_diff(expected: &str, actual: &str) {
+	let a = expected.lines().collect::<Vec<&str>>();
+	let b = actual.lines().collect::<Vec<&str>>();
+	// lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+	let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+	for i in (0..a.len()).rev() {
+		for j in (0..b.len()).rev() {
+			lcs[i][j] = if a[i] == b[j] {
+				lcs[i + 1][j + 1] + 1
+			} else {
+				::std::cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+			};
+		}
+	}
+	let (mut i, mut j) = (0, 0);
+	while i < a.len() || j < b.len() {
+		if i < a.len() && j < b.len() && a[i] == b[j] {
+			println!("      {}", a[i]);
+			i += 1;
+			j += 1;
+		} else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+			println!("    - {}", a[i]);
+			i += 1;
+		} else {
+			println!("    + {}", b[j]);
+			j += 1;
+		}
+	}
+}
+
+/// Compares what a program printed against the file next to it with the given
+/// extension, printing the differences if they don't match. Returns true if
+/// they matched.
+fn compare(path: &Path, ext: &str, actual: io::Result<String>) -> bool {
+	let name = match ext {
+		"expected" => format!("{}", path.display()),
+		_ => format!("{} ({})", path.display(), ext)
+	};
+	let expected = match read_file(&path.with_extension(ext)) {
+		Ok(val) => normalize(&val),
+		Err(err) => {
+			println!("FAIL {} (could not read expected output: {})", name, err);
+			return false;
+		}
+	};
+	let actual = match actual {
+		Ok(val) => normalize(&val),
+		Err(err) => {
+			println!("FAIL {} (could not read program: {})", name, err);
+			return false;
+		}
+	};
+	if expected != actual {
+		println!("FAIL {}", name);
+		print_diff(&expected, &actual);
+	}
+	expected == actual
+}
+
+/// Runs a single test, returning true if it passed. Programs with a .check
+/// file are also checked without running them, and the warnings compared
+/// against it.
+fn run_test(path: &Path, options: &Options) -> bool {
+	let options = match test_options(path, options) {
+		Ok(val) => val,
+		Err(err) => {
+			println!("FAIL {} (bad options: {})", path.display(), err);
+			return false;
+		}
+	};
+	let mut passed = compare(path, "expected", run_program(path, &options));
+	if path.with_extension("check").exists() {
+		passed = compare(path, "check", check_program(path, &options)) && passed;
+	}
+	if passed {
+		println!("ok   {}", path.display());
+	}
+	passed
+}
+
+/// Runs every .email file in a directory, comparing its output against the
+/// .expected file next to it, and its warnings against the .check file if
+/// there is one. Returns false if any test failed.
+pub fn run_tests(dir: &str, options: &Options) -> bool {
+	let entries = match fs::read_dir(dir) {
+		Ok(val) => val,
+		Err(err) => {
+			println!("couldn't open {}: {}", dir, err);
+			return false;
+		}
+	};
+	let mut paths = entries.filter_map(|v|v.ok())
+	                       .map(|v|v.path())
+	                       .filter(|v|v.extension().map(|ext|ext == "email").unwrap_or(false))
+	                       .collect::<Vec<PathBuf>>();
+	paths.sort();
+
+	let mut failed = 0;
+	for path in &paths {
+		if !run_test(path, options) {
+			failed += 1;
+		}
+	}
+	println!("");
+	println!("{} passed, {} failed, {} total", paths.len() - failed, failed, paths.len());
+	failed == 0
+}
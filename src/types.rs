@@ -4,7 +4,8 @@ use interpreter::Interpreter;
 use mail::Draft;
 use environment::Environment;
 use std::str::FromStr;
-use modifier::ModifierFunc;
+use error::{RuntimeError, RuntimeErrorType};
+use std::fmt;
 
 #[derive(Clone, Debug)]
 pub enum Type {
@@ -15,129 +16,175 @@ pub enum Type {
 	Expression(Box<Instruction>)
 }
 
+impl fmt::Display for Type {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Type::Null => write!(f, "null"),
+			Type::Text(ref s) => write!(f, "{:?}", s),
+			Type::UserPath(ref name, ref server) => {
+				try!(write!(f, "<"));
+				try!(name.fmt_inner(f));
+				try!(write!(f, "@"));
+				try!(server.fmt_inner(f));
+				write!(f, ">")
+			},
+			Type::Tuple(ref t) => {
+				try!(write!(f, "("));
+				for (i, v) in t.iter().enumerate() {
+					if i > 0 {
+						try!(write!(f, ", "));
+					}
+					try!(write!(f, "{}", v));
+				}
+				if t.len() <= 1 {
+					try!(write!(f, ","));
+				}
+				write!(f, ")")
+			},
+			Type::Expression(_) => write!(f, "expression")
+		}
+	}
+}
+
+/// Converts a possibly negative position into an absolute position.
+fn wrap_pos(pos: isize, len: usize) -> isize {
+	if pos < 0 {
+		(len as isize) + pos
+	} else {
+		pos
+	}
+}
+
 impl Type {
 	pub fn get_num<T>(&self, inter: &mut Interpreter, from: &UserPath,
-	                  env: &mut Environment) -> Option<T>
+	                  env: &mut Environment) -> Result<T, RuntimeError>
 	where T: FromStr {
 		match *self {
-			Type::Text(ref s) => s.parse::<T>().ok(),
-			Type::Expression(_) => self.resolve(inter, from, env).get_num(inter, from, env),
-			_ => None
+			Type::Text(ref s) => s.parse::<T>().map_err(
+				|_|RuntimeError::new(RuntimeErrorType::NotANumber(s.clone()))),
+			Type::Expression(_) => try!(self.resolve(inter, from, env)).get_num(inter, from, env),
+			ref other => Err(other.mismatch("number"))
 		}
 	}
 
 	fn get_modname(&self, inter: &mut Interpreter, from: &UserPath,
-	               env: &mut Environment) -> String {
+	               env: &mut Environment) -> Result<String, RuntimeError> {
 		match *self {
-			Type::Text(ref s) => s.clone(),
-			Type::Tuple(ref t) => t[0].get_string(inter, from, env).unwrap(),
-			Type::Expression(_) => self.resolve(inter, from, env).get_modname(inter, from, env),
-			_ => panic!()
+			Type::Text(ref s) => Ok(s.clone()),
+			Type::Tuple(ref t) => match t.get(0) {
+				Some(name) => name.get_string(inter, from, env),
+				None => Err(RuntimeError::new(RuntimeErrorType::UnknownModifier("".to_string())))
+			},
+			Type::Expression(_) => try!(self.resolve(inter, from, env)).get_modname(inter, from, env),
+			ref other => Err(other.mismatch("modifier"))
 		}
 	}
 
 	fn get_modargs(&self, inter: &mut Interpreter, from: &UserPath,
-	               env: &mut Environment) -> Vec<Type> {
+	               env: &mut Environment) -> Result<Vec<Type>, RuntimeError> {
 		match *self {
-			Type::Text(_) => Vec::new(),
-			Type::Tuple(ref t) => t[1..].to_vec(),
-			Type::Expression(_) => self.resolve(inter, from, env).get_modargs(inter, from, env),
-			_ => panic!()
+			Type::Text(_) => Ok(Vec::new()),
+			Type::Tuple(ref t) => Ok(t[1..].to_vec()),
+			Type::Expression(_) => try!(self.resolve(inter, from, env)).get_modargs(inter, from, env),
+			ref other => Err(other.mismatch("modifier"))
 		}
 	}
 
 	pub fn modify(&self, other: &Type, inter: &mut Interpreter, from: &UserPath,
-	              env: &mut Environment) -> Option<Type> {
-		let mod_name = self.get_modname(inter, from, env);
-		let mod_args = self.get_modargs(inter, from, env);
-		// yet another hack here :(
-		let ptr;
-		match inter.modifiers.get(&mod_name) {
-			Some(func) => {
-				use std::borrow::Borrow;
-				ptr = func.borrow() as *const ModifierFunc;
-			},
-			None => {return None;}
-		}
-		unsafe {
-			(*ptr)(other, inter, from, env, &mod_args)
-		}
+	              env: &mut Environment) -> Result<Type, RuntimeError> {
+		let modifier = try!(self.resolve(inter, from, env));
+		let mod_name = try!(modifier.get_modname(inter, from, env));
+		let mod_args = try!(modifier.get_modargs(inter, from, env));
+		// Clone the modifier out, so the interpreter can be borrowed by it
+		let func = match inter.modifiers.get(&mod_name) {
+			Some(func) => func.clone(),
+			None => return Err(RuntimeError::new(RuntimeErrorType::UnknownModifier(mod_name)))
+		};
+		func(other, inter, from, env, &mod_args)
 	}
 
 	pub fn get_bool(&self, inter: &mut Interpreter, from: &UserPath,
-	                env: &mut Environment) -> bool {
-		match *self {
+	                env: &mut Environment) -> Result<bool, RuntimeError> {
+		Ok(match *self {
 			Type::Null => false,
 			Type::Text(ref s) => {
 				!["false", "0", ""].contains(&s.to_lowercase().as_str())
 			},
 			Type::Tuple(ref t) => t.len() > 0,
-			Type::Expression(_) => self.resolve(inter, from, env).get_bool(inter, from, env),
+			Type::Expression(_) => {
+				return try!(self.resolve(inter, from, env)).get_bool(inter, from, env);
+			},
 			_ => true
-		}
+		})
 	}
 
-	pub fn resolve(&self, inter: &mut Interpreter, from: &UserPath, env: &mut Environment) -> Type {
+	pub fn resolve(&self, inter: &mut Interpreter, from: &UserPath,
+	               env: &mut Environment) -> Result<Type, RuntimeError> {
 		match *self {
 			Type::Expression(ref exp) => {
-				exp.call(inter, from, env).resolve(inter, from, env)
+				try!(exp.call(inter, from, env)).resolve(inter, from, env)
 			},
 			Type::Tuple(ref tuple) => {
-				Type::Tuple(tuple.iter().map(|v|v.resolve(inter, from, env)).collect())
+				let mut ret = Vec::with_capacity(tuple.len());
+				for v in tuple {
+					ret.push(try!(v.resolve(inter, from, env)));
+				}
+				Ok(Type::Tuple(ret))
 			},
-			ref other => other.clone()
+			ref other => Ok(other.clone())
 		}
 	}
 
 	pub fn len(&self, inter: &mut Interpreter, from: &UserPath,
-	           env: &mut Environment) -> Option<usize> {
+	           env: &mut Environment) -> Result<usize, RuntimeError> {
 		match *self {
-			Type::Tuple(ref vec) => Some(vec.len()),
-			Type::Text(ref text) => Some(text.chars().count()),
-			Type::Expression(_) => self.resolve(inter, from, env).len(inter, from, env),
-			_ => None
+			Type::Tuple(ref vec) => Ok(vec.len()),
+			Type::Text(ref text) => Ok(text.chars().count()),
+			Type::Expression(_) => try!(self.resolve(inter, from, env)).len(inter, from, env),
+			ref other => Err(other.mismatch("text or tuple"))
 		}
 	}
 
 	pub fn index(&self, pos: isize, inter: &mut Interpreter, from: &UserPath,
-	             env: &mut Environment) -> Option<Type> {
-		let selflen = self.len(inter, from, env).unwrap();
-		let pos = if pos < 0 {
-			((selflen as isize) + pos) as usize
-		} else {
-			pos as usize
-		};
+	             env: &mut Environment) -> Result<Type, RuntimeError> {
+		if let Type::Expression(_) = *self {
+			return try!(self.resolve(inter, from, env)).index(pos, inter, from, env);
+		}
+		let selflen = try!(self.len(inter, from, env));
+		let abspos = wrap_pos(pos, selflen);
+		if abspos < 0 || abspos as usize >= selflen {
+			return Err(RuntimeError::new(RuntimeErrorType::IndexOutOfRange(pos, selflen)));
+		}
+		let abspos = abspos as usize;
 		match *self {
-			Type::Tuple(ref vec) => Some(vec[pos].clone()),
-			Type::Text(ref text) => Some(Type::Text(text.chars().nth(pos).unwrap().to_string())),
-			Type::Expression(_) => self.resolve(inter, from, env)
-			                           .index(pos as isize, inter, from, env),
-			_ => None
+			Type::Tuple(ref vec) => Ok(vec[abspos].clone()),
+			Type::Text(ref text) => Ok(Type::Text(text.chars().nth(abspos).unwrap().to_string())),
+			ref other => Err(other.mismatch("text or tuple"))
 		}
 	}
 
 	pub fn slice(&self, a: isize, b: isize, inter: &mut Interpreter, from: &UserPath,
-	             env: &mut Environment) -> Option<Type> {
-		let selflen = self.len(inter, from, env).unwrap();
-		let a = if a < 0 {
-			((selflen as isize) + a) as usize
-		} else {
-			a as usize
-		};
-		let b = if b < 0 {
-			((selflen as isize) + b) as usize
-		} else {
-			b as usize
-		};
+	             env: &mut Environment) -> Result<Type, RuntimeError> {
+		if let Type::Expression(_) = *self {
+			return try!(self.resolve(inter, from, env)).slice(a, b, inter, from, env);
+		}
+		let selflen = try!(self.len(inter, from, env));
+		let absa = wrap_pos(a, selflen);
+		let absb = wrap_pos(b, selflen);
+		if absa < 0 || absa as usize > selflen {
+			return Err(RuntimeError::new(RuntimeErrorType::IndexOutOfRange(a, selflen)));
+		}
+		if absb < absa || absb as usize > selflen {
+			return Err(RuntimeError::new(RuntimeErrorType::IndexOutOfRange(b, selflen)));
+		}
+		let (absa, absb) = (absa as usize, absb as usize);
 		match *self {
-			Type::Tuple(ref vec) => Some(Type::Tuple(vec[a..b].to_vec())),
+			Type::Tuple(ref vec) => Ok(Type::Tuple(vec[absa..absb].to_vec())),
 			Type::Text(ref text) => {
 				let chars = text.chars();
-				Some(Type::Text(chars.skip(a).take(b-a).collect()))
+				Ok(Type::Text(chars.skip(absa).take(absb-absa).collect()))
 			},
-			Type::Expression(_) => self.resolve(inter, from, env)
-				.slice(a as isize, b as isize, inter, from, env),
-			_ => None
+			ref other => Err(other.mismatch("text or tuple"))
 		}
 	}
 
@@ -149,6 +196,14 @@ impl Type {
 		}
 	}
 
+	pub fn is_tuple(&self) -> bool {
+		if let Type::Tuple(_) = *self {
+			true
+		} else {
+			false
+		}
+	}
+
 	pub fn get_typename(&self) -> &'static str {
 		match *self {
 			Type::Null => "null",
@@ -159,78 +214,94 @@ impl Type {
 		}
 	}
 
+	/// Formats text without quotes, for use inside of userpaths.
+	fn fmt_inner(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Type::Text(ref s) => write!(f, "{}", s),
+			ref other => write!(f, "{}", other)
+		}
+	}
+
+	/// Creates a type mismatch error for when this type is not what was expected.
+	pub fn mismatch(&self, expected: &'static str) -> RuntimeError {
+		RuntimeError::new(RuntimeErrorType::TypeMismatch(expected, self.get_typename()))
+	}
+
 	pub fn get_string(&self, inter: &mut Interpreter, from: &UserPath,
-	                  env: &mut Environment) -> Option<String> {
+	                  env: &mut Environment) -> Result<String, RuntimeError> {
 		match *self {
-			Type::Text(ref val) => Some(val.clone()),
-			Type::Expression(_) => self.resolve(inter, from, env).get_string(inter, from, env),
-			Type::UserPath(ref name, ref server) => Some(
-				format!("{}@{}", name.get_string(inter, from, env).unwrap(),
-				                 server.get_string(inter, from, env).unwrap())),
-			_ => None
+			Type::Text(ref val) => Ok(val.clone()),
+			Type::Expression(_) => try!(self.resolve(inter, from, env)).get_string(inter, from, env),
+			Type::UserPath(ref name, ref server) => Ok(
+				format!("{}@{}", try!(name.get_string(inter, from, env)),
+				                 try!(server.get_string(inter, from, env)))),
+			ref other => Err(other.mismatch("text"))
+		}
+	}
+
+	/// Like get_string, but tuples and null values become an empty string.
+	fn get_string_lossy(&self, inter: &mut Interpreter, from: &UserPath,
+	                    env: &mut Environment) -> Result<String, RuntimeError> {
+		match try!(self.resolve(inter, from, env)) {
+			Type::Tuple(_) | Type::Null => Ok("".to_string()),
+			other => other.get_string(inter, from, env)
 		}
 	}
 
 	pub fn get_tuple(&self, inter: &mut Interpreter, from: &UserPath,
-	                 env: &mut Environment) -> Option<Vec<Type>> {
+	                 env: &mut Environment) -> Result<Vec<Type>, RuntimeError> {
 		match *self {
-			Type::Tuple(ref v) => Some(v.clone()),
-			Type::Expression(_) => self.resolve(inter, from, env).get_tuple(inter, from, env),
-			_ => None
+			Type::Tuple(ref v) => Ok(v.clone()),
+			Type::Expression(_) => try!(self.resolve(inter, from, env)).get_tuple(inter, from, env),
+			ref other => Err(other.mismatch("tuple"))
 		}
 	}
 
 	pub fn unpack(&self, inter: &mut Interpreter, from: &UserPath,
-	              env: &mut Environment) -> Vec<Type> {
-		match self.get_tuple(inter, from, env) {
-			Some(v) => v,
-			None => vec![self.clone()]
-		}
+	              env: &mut Environment) -> Result<Vec<Type>, RuntimeError> {
+		Ok(match try!(self.resolve(inter, from, env)) {
+			Type::Tuple(v) => v,
+			other => vec![other]
+		})
 	}
 
 	pub fn get_draft(&self, inter: &mut Interpreter, from: &UserPath,
-	                 env: &mut Environment) -> Option<Draft> {
+	                 env: &mut Environment) -> Result<Draft, RuntimeError> {
 		match *self {
 			Type::Tuple(ref t) => {
-				Some(Draft {
-					subject: t.get(0).map(
-						|v|v.get_string(inter, from, env).unwrap_or("".to_string())
-					).unwrap_or("".to_string()),
-					message: t.get(1).map(
-						|v|v.get_string(inter, from, env).unwrap_or("".to_string())
-					).unwrap_or("".to_string()),
-					attachments: (2..).take_while(|v|*v<t.len()).map(
-						|v|t[v].get_string(inter, from, env).unwrap_or("".to_string())
-					).collect()
+				let mut fields = Vec::with_capacity(t.len());
+				for v in t {
+					fields.push(try!(v.get_string_lossy(inter, from, env)));
+				}
+				let mut fields = fields.into_iter();
+				Ok(Draft {
+					subject: fields.next().unwrap_or("".to_string()),
+					message: fields.next().unwrap_or("".to_string()),
+					attachments: fields.collect()
 				})
 			},
 			Type::Text(ref val) => {
-				Some(Draft {
+				Ok(Draft {
 					subject: val.to_string(),
 					message: "".to_string(),
 					attachments: Vec::new()
 				})
 			},
-			Type::Expression(_) => self.resolve(inter, from, env).get_draft(inter, from, env),
-			_ => None
+			Type::Expression(_) => try!(self.resolve(inter, from, env)).get_draft(inter, from, env),
+			ref other => Err(other.mismatch("text or tuple"))
 		}
 	}
 
 	pub fn get_user(&self, inter: &mut Interpreter, from: &UserPath,
-	                env: &mut Environment) -> Option<UserPath> {
+	                env: &mut Environment) -> Result<UserPath, RuntimeError> {
 		match *self {
 			Type::UserPath(ref name, ref server) => {
-				let a = name.get_string(inter, from, env);
-				let b = server.get_string(inter, from, env);
-				match (a, b) {
-					(Some(a), Some(b)) => {
-						Some(UserPath(a, b))
-					},
-					_ => None
-				}
+				let a = try!(name.get_string(inter, from, env));
+				let b = try!(server.get_string(inter, from, env));
+				Ok(UserPath(a, b))
 			},
-			Type::Expression(_) => self.resolve(inter, from, env).get_user(inter, from, env),
-			_ => None
+			Type::Expression(_) => try!(self.resolve(inter, from, env)).get_user(inter, from, env),
+			ref other => Err(other.mismatch("user"))
 		}
 	}
 }
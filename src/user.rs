@@ -1,14 +1,22 @@
 use interpreter::Interpreter;
 use mail::Mail;
 use instruction::Instruction;
-use std::rc::Rc;
+use std::sync::Arc;
 use std::fmt;
 use types::Type;
 use environment::Environment;
+use error::{RuntimeError, HandlerContext};
 use regex;
+use interpreter::trace::Outcome;
+use mail::BounceReason;
+
+/// The variables that are set before a handler is run, which capture groups
+/// can not be named.
+pub const RESERVED_NAMES: &'static [&'static str] =
+	&["subject", "content", "sender", "self", "attachments", "match"];
 
 /// A Tuple that represents a username + servername combo.
-#[derive(Clone)]
+#[derive(Clone, PartialEq)]
 pub struct UserPath(pub String, pub String);
 
 impl fmt::Debug for UserPath {
@@ -33,6 +41,17 @@ impl UserPath {
 			"anon".to_string()
 		)
 	}
+	/// Returns true if this is the anonymous UserPath
+	pub fn is_anon(&self) -> bool {
+		*self == UserPath::new_anon()
+	}
+	/// Creates a new UserPath to the mailer daemon, who sends bounced mail
+	pub fn new_mailer_daemon() -> UserPath {
+		UserPath (
+			"mailer-daemon".to_string(),
+			"std.com".to_string()
+		)
+	}
 	/// Create a type from this UserPath
 	pub fn create_type(&self) -> Type {
 		Type::UserPath (
@@ -44,7 +63,10 @@ impl UserPath {
 
 #[derive(Clone)]
 pub struct UserDef {
-	pub func: Rc<UserType>
+	pub func: Arc<UserType>,
+	/// If true, every handler that matches a subject is run, instead of only
+	/// the first.
+	pub all_matches: bool
 }
 
 impl fmt::Debug for UserDef {
@@ -56,21 +78,71 @@ impl fmt::Debug for UserDef {
 impl UserDef {
 	pub fn create_def_external(func: Box<UserExtFunc>) -> UserDef {
 		UserDef {
-			func: Rc::new(UserType::External(func))
+			func: Arc::new(UserType::External(func)),
+			all_matches: false
 		}
 	}
 
-	pub fn create_def_internal(instructions: Vec<(String, Vec<Instruction>)>)
-	-> UserDef {
-		UserDef {
-			func: Rc::new(UserType::Internal(instructions.iter().map(
-				|v|(regex::Regex::new(&v.0).unwrap(), v.1.clone())).collect()))
+	/// Creates a user from its handlers, where a handler without a regex is
+	/// the else block. If all_matches is true, every handler that matches a
+	/// subject is run, instead of only the first. Fails if a regex is invalid.
+	pub fn create_def_internal(instructions: Vec<(Option<String>, Vec<Instruction>, Option<(usize, usize)>)>,
+	                           all_matches: bool) -> Result<UserDef, regex::Error> {
+		let mut handlers = Vec::new();
+		for (regex, block, pos) in instructions {
+			handlers.push(Handler {
+				regex: match regex {
+					Some(ref val) => Some(try!(regex::Regex::new(val))),
+					None => None
+				},
+				block: block,
+				pos: pos,
+				file: None
+			});
+		}
+		Ok(UserDef {
+			func: Arc::new(UserType::Internal(handlers)),
+			all_matches: all_matches
+		})
+	}
+
+	/// Creates a definition with this definition's handlers, followed by the
+	/// parent's, so that subjects this one doesn't match fall through to the
+	/// parent. The parent's else block is only used if this one has none, and
+	/// whether every matching handler is run is decided by this definition
+	/// alone. Returns None if either is a native user.
+	pub fn extend(&self, parent: &UserDef) -> Option<UserDef> {
+		match (&*self.func, &*parent.func) {
+			(&UserType::Internal(ref handlers), &UserType::Internal(ref parent_handlers)) => {
+				Some(UserDef {
+					func: Arc::new(UserType::Internal(
+						handlers.iter().chain(parent_handlers.iter()).cloned().collect())),
+					all_matches: self.all_matches
+				})
+			},
+			_ => None
+		}
+	}
+
+	/// Marks this user's handlers, and users defined in them, as defined in
+	/// the given imported file. Handlers that already have a file keep it.
+	pub fn set_file(&mut self, file: &str) {
+		if let Some(&mut UserType::Internal(ref mut handlers)) = Arc::get_mut(&mut self.func) {
+			for handler in handlers {
+				if handler.file.is_none() {
+					handler.file = Some(file.to_string());
+				}
+				for inst in &mut handler.block {
+					inst.set_file(file);
+				}
+			}
 		}
 	}
 
 	pub fn create_user(&self) -> User {
 		User {
 			func: self.func.clone(),
+			all_matches: self.all_matches,
 			env: Environment::new()
 		}
 	}
@@ -78,7 +150,8 @@ impl UserDef {
 
 #[derive(Clone)]
 pub struct User {
-	pub func: Rc<UserType>,
+	pub func: Arc<UserType>,
+	pub all_matches: bool,
 	pub env: Environment
 }
 
@@ -91,11 +164,15 @@ impl fmt::Debug for User {
 impl User {
 	pub fn get_userdef(&self) -> UserDef {
 		UserDef {
-			func: self.func.clone()
+			func: self.func.clone(),
+			all_matches: self.all_matches
 		}
 	}
 
-	pub fn send(&mut self, mut inter: &mut Interpreter, mail: &Mail) {
+	/// Sends mail to this user, returning what accepted it, or
+	/// `BounceReason::NoHandler` if nothing did. The else block is only run
+	/// if no handler matched.
+	pub fn send(&mut self, mut inter: &mut Interpreter, mail: &Mail) -> Result<Outcome, RuntimeError> {
 		self.env.set("subject", Type::Text(mail.subject.clone()));
 		self.env.set("content", Type::Text(mail.message.clone()));
 		self.env.set("sender", mail.from.create_type());
@@ -110,20 +187,103 @@ impl User {
 				(**b)(self, &mut inter, &mail);
 			},
 			UserType::Internal(ref v) => {
-				for matcher in v {
-					if matcher.0.is_match(&mail.subject) {
-						inter.run(&matcher.1, &mail.to, &mut self.env);
-						break;
+				let mut matched = None;
+				for handler in v.iter().filter(|v|v.regex.is_some()) {
+					if handler.capture(&mail.subject, &mut self.env) {
+						try!(inter.run(&handler.block, &mail.to, &mut self.env)
+							.map_err(|err|handler.annotate(err, &mail.to)));
+						if matched.is_none() {
+							matched = handler.regex.as_ref().map(|v|v.as_str().to_string());
+						}
+						if !inter.take_fallthrough() && !self.all_matches {
+							break;
+						}
 					}
 				}
+				if let Some(regex) = matched {
+					return Ok(Outcome::Handler(regex));
+				}
+				return Ok(match v.iter().find(|v|v.regex.is_none()) {
+					Some(handler) => {
+						handler.capture(&mail.subject, &mut self.env);
+						try!(inter.run(&handler.block, &mail.to, &mut self.env)
+							.map_err(|err|handler.annotate(err, &mail.to)));
+						// There is no handler left to continue to
+						inter.take_fallthrough();
+						Outcome::CatchAll
+					},
+					None => Outcome::Undelivered(BounceReason::NoHandler)
+				});
 			}
 		}
+		Ok(Outcome::Native)
 	}
 }
 
-pub type UserExtFunc = Fn(&User, &mut Interpreter, &Mail);
+pub type UserExtFunc = dyn Fn(&User, &mut Interpreter, &Mail) + Send + Sync;
+
+/// A subject regex and the block of code to run when a subject matches it.
+/// The else block of a user has no regex, and matches any subject.
+#[derive(Clone)]
+pub struct Handler {
+	pub regex: Option<regex::Regex>,
+	pub block: Vec<Instruction>,
+	pub pos: Option<(usize, usize)>,
+	/// The imported file the handler is defined in, or None for the file
+	/// being run.
+	pub file: Option<String>
+}
+
+impl Handler {
+	/// If the subject matches this handler's regex, stores the capture groups
+	/// in the environment and returns true. Numbered groups are stored as a
+	/// tuple in `match`, and named groups are stored under their own names.
+	/// The else block stores the whole subject as the only group.
+	pub fn capture(&self, subject: &str, env: &mut Environment) -> bool {
+		let regex = match self.regex {
+			Some(ref val) => val,
+			None => {
+				env.set("match", Type::Tuple(vec![Type::Text(subject.to_string())]));
+				return true;
+			}
+		};
+		let caps = match regex.captures(subject) {
+			Some(val) => val,
+			None => return false
+		};
+		env.set("match", Type::Tuple((0..caps.len())
+			.map(|i|Type::Text(caps.at(i).unwrap_or("").to_string()))
+			.collect()
+		));
+		for name in regex.capture_names() {
+			if let Some(name) = name {
+				env.set(name, Type::Text(caps.name(name).unwrap_or("").to_string()));
+			}
+		}
+		true
+	}
+
+	/// Adds this handler, as owned by the given user, and the file it is in
+	/// to an error that occurred in it.
+	pub fn annotate(&self, err: RuntimeError, user: &UserPath) -> RuntimeError {
+		let err = err.or_handler(self.get_context(user));
+		match self.file {
+			Some(ref file) => err.or_file(file),
+			None => err
+		}
+	}
+
+	/// Describes this handler, as owned by the given user, for error reporting.
+	pub fn get_context(&self, user: &UserPath) -> HandlerContext {
+		HandlerContext {
+			user: user.clone(),
+			regex: self.regex.as_ref().map(|v|v.as_str().to_string()),
+			pos: self.pos
+		}
+	}
+}
 
 pub enum UserType {
 	External(Box<UserExtFunc>),
-	Internal(Vec<(regex::Regex, Vec<Instruction>)>)
+	Internal(Vec<Handler>)
 }